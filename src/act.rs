@@ -0,0 +1,395 @@
+// This file/module holds the act subcommand (duff act), which carries out the decisions made in a
+// report. Someone goes through the report (usually as JSON or CSV from duff report) marking each
+// copy "keep" or "remove", then duff act deletes the copies marked for removal, or replaces them
+// with links to a kept copy, or moves them out of the way. Everything is checked before anything
+// is touched: every file in a group being acted on must still have the size and mtime in the
+// report (and optionally the same hash), and if anything doesn't line up we refuse to act at all.
+
+// For sorting rows into groups, and checking, removing, linking and moving files
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+// For comparing mtimes with the ones in the report
+use chrono::{DateTime, Utc};
+
+// For hashing the groups' files in parallel
+use rayon::prelude::*;
+
+// For sizes the user can read
+use pretty_bytes::converter;
+
+// The act subcommand gets its arguments from clap
+use clap::ArgMatches;
+
+use crate::report::{self, ReportRow};
+use crate::util;
+
+
+// The Group struct holds a group from the report that has copies marked for removal.
+struct Group {
+
+    // The group's id, or its number in the report for reports without ids.
+    id : String,
+
+    // The copies to keep (the first is the one links point to) and the ones to remove.
+    keep : Vec<ReportRow>,
+    remove : Vec<ReportRow>,
+}
+
+// The run function is the entry point for the act subcommand. It reads the report, works out what
+// has been marked for removal, checks it all still matches the report, and only then acts.
+// Arguments are as follows:
+// in_args: The ArgMatches for the act subcommand.
+pub fn run(in_args: &ArgMatches) {
+
+    // report is required and mode has a default, so clap will have given us something.
+    let rep_str = in_args.value_of("report").unwrap();
+    let mode = in_args.value_of("mode").unwrap();
+    let move_to = in_args.value_of("move_to").map(PathBuf::from);
+    let verify_hash = in_args.is_present("verify_hash");
+    let dry_run = in_args.is_present("dry_run");
+
+    if (mode == "move") && move_to.is_none() {
+        eprintln!("Moving copies needs a directory to move them to (--move-to).");
+        exit(1)
+    }
+
+    if cfg!(not(unix)) && (mode == "symlink") {
+        eprintln!("Replacing copies with symlinks is only supported on unix-like systems.");
+        exit(1)
+    }
+
+    let (rows, incomplete) = report::read_any_report(rep_str);
+
+    if incomplete {
+        eprintln!("Warning: {} is from a run that was interrupted, so it is incomplete.", rep_str);
+    }
+
+    let (groups, mut problems) = plan(rows);
+
+    if groups.is_empty() && problems.is_empty() {
+        println!("Nothing in {} is marked for removal.", rep_str);
+        exit(0)
+    }
+
+    problems.extend(groups.par_iter()
+        .flat_map_iter(|x| check_group(x, mode, move_to.as_deref(), verify_hash))
+        .collect::<Vec<String>>());
+
+    if !problems.is_empty() {
+        for x in problems.iter() {
+            eprintln!("[Mismatch] {}", x);
+        }
+
+        eprintln!("Refusing to act on {}, {} problem(s) found and nothing has been changed.",
+                  rep_str, problems.len());
+        exit(1)
+    }
+
+    let mut n_done = 0;
+    let mut n_failed = 0;
+    let mut reclaimed: u128 = 0;
+
+    for x in groups.iter() {
+        let keep_path = row_path(&x.keep[0]);
+
+        for y in x.remove.iter() {
+            let curr_path = row_path(y);
+            let dest = move_to.as_ref().map(|t| moved_path(t, &curr_path));
+
+            let verb = match mode {
+                "link" => format!("Hard linked {} to {}", curr_path.display(),
+                                  keep_path.display()),
+                "symlink" => format!("Symlinked {} to {}", curr_path.display(),
+                                     keep_path.display()),
+                "move" => format!("Moved {} to {}", curr_path.display(),
+                                  dest.as_ref().unwrap().display()),
+                _ => format!("Removed {}", curr_path.display())
+            };
+
+            if dry_run {
+                println!("[Dry run] {}", verb);
+                n_done += 1;
+                reclaimed += y.size;
+                continue
+            }
+
+            let act_res = match mode {
+                "link" => replace_with(&curr_path, |tmp| fs::hard_link(&keep_path, tmp)),
+                "symlink" => replace_with(&curr_path, |tmp| make_symlink(&keep_path, tmp)),
+                "move" => move_copy(&curr_path, dest.as_ref().unwrap()),
+                _ => remove_copy(&curr_path)
+            };
+
+            match act_res {
+                Ok(_) => {
+                    println!("[{}] {}", x.id, verb);
+                    n_done += 1;
+                    reclaimed += y.size;
+                }
+                Err(e) => {
+                    eprintln!("[Action error] {}: {}", curr_path.display(), e);
+                    n_failed += 1;
+                }
+            }
+        }
+    }
+
+    println!("{:<40} {:>1}", "Copies Acted On:", n_done);
+    println!("{:<40} {:>1}", "Space Reclaimed:", converter::convert(reclaimed as f64));
+
+    if n_failed > 0 {
+        println!("{:<40} {:>1}", "Copies That Failed:", n_failed);
+        exit(1)
+    }
+}
+
+// The plan function sorts the report's rows into groups, keeping only the groups with copies
+// marked for removal, and returns them along with any problems with the markers themselves (ones
+// we don't understand, or groups with every copy marked for removal).
+// Arguments are as follows:
+// rows: The report's rows.
+fn plan(rows: Vec<ReportRow>) -> (Vec<Group>, Vec<String>) {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut problems = Vec::new();
+
+    // Where each group is in groups, by its id, so finding a row's group doesn't mean going
+    // through every group for every row.
+    let mut group_pos: HashMap<String, usize> = HashMap::new();
+
+    for x in rows {
+        let id = x.group_id.clone().unwrap_or_else(|| x.group.to_string());
+
+        let marker = x.action.as_deref().map(|t| t.trim().to_lowercase()).unwrap_or_default();
+
+        let pos = match group_pos.get(&id) {
+            Some(t) => *t,
+            None => {
+                group_pos.insert(id.clone(), groups.len());
+                groups.push(Group {id, keep : Vec::new(), remove : Vec::new()});
+                groups.len() - 1
+            }
+        };
+
+        match marker.as_str() {
+            "keep" => groups[pos].keep.push(x),
+            "remove" => groups[pos].remove.push(x),
+            "" => (),
+            _ => problems.push(format!("{}: unknown action {}, expected keep or remove",
+                                       row_path(&x).display(), marker))
+        }
+    }
+
+    groups.retain(|x| !x.remove.is_empty());
+
+    for x in groups.iter().filter(|x| x.keep.is_empty()) {
+        problems.push(format!("group {}: every copy is marked for removal, at least one has to be \
+                              kept", x.id));
+    }
+
+    (groups, problems)
+}
+
+// The check_group function checks every copy in a group against the report, returning a line for
+// each problem found: copies that are gone, have a different size or mtime, directories that
+// aren't empty anymore, moves that would overwrite something, and (if asked for) copies whose
+// hash doesn't match the rest of the group or the group's id.
+// Arguments are as follows:
+// curr_group: The group to check.
+// mode: What is being done with the copies marked for removal.
+// move_to: Where copies are being moved to, if they are.
+// verify_hash: Whether to re-hash the copies.
+fn check_group(curr_group: &Group, mode: &str, move_to: Option<&Path>,
+               verify_hash: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut hashes = Vec::new();
+
+    for x in curr_group.keep.iter().chain(curr_group.remove.iter()) {
+        let curr_path = row_path(x);
+
+        let curr_meta = match fs::metadata(&curr_path) {
+            Ok(t) => t,
+            Err(e) => {
+                problems.push(format!("{}: {}", curr_path.display(), e));
+                continue
+            }
+        };
+
+        if let Some(t) = changed(x, &curr_meta) {
+            problems.push(format!("{}: {}", curr_path.display(), t));
+            continue
+        }
+
+        if curr_meta.is_dir() {
+            if fs::read_dir(&curr_path).map_or(true, |mut t| t.next().is_some()) {
+                problems.push(format!("{}: directory is no longer empty", curr_path.display()));
+            }
+
+            if (mode == "link") || (mode == "symlink") {
+                problems.push(format!("{}: directories can't be replaced with links",
+                                      curr_path.display()));
+            }
+            continue
+        }
+
+        if verify_hash {
+            match util::build_result(&curr_path, Some(curr_meta), false) {
+                Some((mut t, _)) => match t.calc_hash(524288) {
+                    Ok(_) => hashes.push((curr_path.clone(), t.hash)),
+                    Err(e) => problems.push(format!("{}: {}", curr_path.display(), e))
+                },
+                None => problems.push(format!("{}: couldn't be examined", curr_path.display()))
+            }
+        }
+    }
+
+    // A copy reached through a symlink can be the very file that's being kept, removing it would
+    // leave nothing behind.
+    let kept: Vec<PathBuf> = curr_group.keep.iter()
+        .filter_map(|x| fs::canonicalize(row_path(x)).ok())
+        .collect();
+
+    for x in curr_group.remove.iter() {
+        if fs::canonicalize(row_path(x)).is_ok_and(|t| kept.contains(&t)) {
+            problems.push(format!("{}: is the same file as a copy being kept",
+                                  row_path(x).display()));
+        }
+    }
+
+    if let Some(t) = move_to {
+        for x in curr_group.remove.iter() {
+            let dest = moved_path(t, &row_path(x));
+
+            if dest.exists() {
+                problems.push(format!("{}: {} already exists", row_path(x).display(),
+                                      dest.display()));
+            }
+        }
+    }
+
+    // Every copy should have the same hash, and unless they are empty (which never get hashed
+    // during a run), it should be the one the group's id came from (target groups add a part for
+    // the target after a -, see util::group_id). A group can reach here with nothing kept (plan
+    // reports that separately), so the first row is taken from either list.
+    let first_row = curr_group.keep.iter().chain(curr_group.remove.iter()).next();
+
+    if let (Some((_, first)), Some(first_row)) = (hashes.first(), first_row) {
+        let id_matches = (first_row.size == 0) || first_row.group_id.as_ref()
+            .is_none_or(|t| util::hash_id(first).as_deref() == t.split('-').next());
+
+        for (x, y) in hashes.iter() {
+            if (y != first) || !id_matches {
+                problems.push(format!("{}: content doesn't match the rest of group {}",
+                                      x.display(), curr_group.id));
+            }
+        }
+    }
+
+    problems
+}
+
+// The changed function compares a file's metadata with its row in the report, returning what
+// changed if anything did.
+// Arguments are as follows:
+// curr_row: The file's row in the report.
+// curr_meta: The file's metadata now.
+fn changed(curr_row: &ReportRow, curr_meta: &Metadata) -> Option<String> {
+    if !curr_meta.is_dir() && (u128::from(curr_meta.len()) != curr_row.size) {
+        return Some(format!("size is {} B, but the report says {} B", curr_meta.len(),
+                            curr_row.size));
+    }
+
+    let mtime: DateTime<Utc> = match curr_meta.modified() {
+        Ok(t) => t.into(),
+        Err(e) => return Some(e.to_string())
+    };
+
+    if mtime.to_string() != curr_row.mtime {
+        return Some(format!("modified at {}, but the report says {}", mtime, curr_row.mtime));
+    }
+
+    None
+}
+
+// The row_path function gives the full path of the file in a report row, undoing the escapes
+// reports write paths with (see util::escape_path).
+fn row_path(curr_row: &ReportRow) -> PathBuf {
+    PathBuf::from(util::unescape_path(&curr_row.path)).join(util::unescape_path(&curr_row.name))
+}
+
+// The moved_path function gives where a copy ends up when moved to move_to, its whole path is
+// recreated under move_to so copies with the same name don't collide.
+// Arguments are as follows:
+// move_to: The directory copies are being moved to.
+// curr_path: The copy's path.
+fn moved_path(move_to: &Path, curr_path: &Path) -> PathBuf {
+    let rel_path: PathBuf = curr_path.components()
+        .filter(|x| matches!(x, std::path::Component::Normal(_)))
+        .collect();
+
+    move_to.join(rel_path)
+}
+
+// The remove_copy function deletes a copy, or the empty directory.
+fn remove_copy(curr_path: &Path) -> io::Result<()> {
+    if curr_path.is_dir() {
+        fs::remove_dir(curr_path)
+    } else {
+        fs::remove_file(curr_path)
+    }
+}
+
+// The replace_with function swaps a copy out for a link, made by make_link at a temporary path
+// next to the copy and then renamed over it, so the copy is never just gone if something fails.
+// Arguments are as follows:
+// curr_path: The copy to replace.
+// make_link: Makes the link at the path it is given.
+fn replace_with<F: Fn(&Path) -> io::Result<()>>(curr_path: &Path, make_link: F) -> io::Result<()> {
+    let mut tmp_name = curr_path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".duff_tmp");
+    let tmp_path = curr_path.with_file_name(tmp_name);
+
+    make_link(&tmp_path)?;
+
+    fs::rename(&tmp_path, curr_path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+// The make_symlink function creates a symlink at link_path pointing at target.
+#[cfg(unix)]
+fn make_symlink(target: &Path, link_path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link_path)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(_target: &Path, _link_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "symlinks are only supported on unix-like systems"))
+}
+
+// The move_copy function moves a copy (or empty directory) to dest, creating any directories it
+// needs. Renaming only works within a filesystem, so anywhere else the copy is copied over and
+// then removed.
+// Arguments are as follows:
+// curr_path: The copy to move.
+// dest: Where to move it to.
+fn move_copy(curr_path: &Path, dest: &Path) -> io::Result<()> {
+    if let Some(t) = dest.parent() {
+        fs::create_dir_all(t)?;
+    }
+
+    if fs::rename(curr_path, dest).is_ok() {
+        return Ok(());
+    }
+
+    if curr_path.is_dir() {
+        fs::create_dir_all(dest)?;
+    } else {
+        fs::copy(curr_path, dest)?;
+    }
+
+    remove_copy(curr_path)
+}
@@ -0,0 +1,692 @@
+// This file/module contains the functions for reading and writing archive files (the .arch files
+// saved when the archive flag is on) and log files, along with the archive maintenance subcommands
+// (duff archive merge/prune/verify/stats/migrate) that let archives be kept around as long-lived
+// assets.
+//
+// Both archives and logs start with a header record describing the file (format version, DuFF
+// version, hash algorithm, host and search roots) followed by one FileResult record per line.
+// Lines starting with # are comments meant for humans and are skipped. Files written before we had
+// headers are read as format version 1.
+
+// I sometimes use extra parens to make thing more readable to me
+#![allow(unused_parens)]
+
+use crate::util;
+use crate::config::Config;
+use crate::file_result::{self, FileResult};
+
+// For reading and writing archives.
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// For collating archive entries
+use std::collections::HashMap;
+
+// To quit early if errors are detected that cannot be dealt with.
+use std::process::exit;
+
+// For re-hashing entries in parallel when verifying.
+use rayon::iter::{ParallelIterator, IntoParallelRefIterator};
+
+// For mtime comparisons and header timestamps
+use chrono::{DateTime, Utc};
+
+// For the header record, and for migrating records from older format versions
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+// For printing out human friendly sizes in the stats
+use pretty_bytes::converter;
+
+// The archive subcommands get their arguments from clap
+use clap::ArgMatches;
+
+
+// The run function is the entry point for the archive subcommand, it just hands the work off to
+// the function for whichever archive subcommand the user asked for.
+// Arguments are as follows:
+// in_args: The ArgMatches for the archive subcommand.
+pub fn run(in_args: &ArgMatches) {
+    match in_args.subcommand() {
+        ("merge", Some(sub_args)) => merge(sub_args),
+        ("prune", Some(sub_args)) => prune(sub_args),
+        ("verify", Some(sub_args)) => verify(sub_args),
+        ("stats", Some(sub_args)) => stats(sub_args),
+        ("migrate", Some(sub_args)) => migrate(sub_args),
+
+        // clap makes sure one of the subcommands above was given
+        _ => unreachable!()
+    }
+}
+
+// The current archive and log format version. Bump this whenever FileResult (or anything else
+// about the format) changes, and add a step to migrate_record so older files can still be read.
+pub const FORMAT_VERSION: u32 = 4;
+
+// The Header struct is the first record in every archive and log file, describing what is in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+
+    // The version of the format the rest of the file is written in (see FORMAT_VERSION).
+    pub format_version : u32,
+
+    // The version of DuFF that wrote the file.
+    pub duff_version : String,
+
+    // The hash algorithm used for the hashes in the file, hashes from a different algorithm can't
+    // be compared with ours.
+    pub hash_algorithm : String,
+
+    // The host the file was written on.
+    pub host : String,
+
+    // The search (and reference) directories of the run that wrote the file, written the same way
+    // as the paths in the records so ones that aren't valid UTF-8 come back as they were.
+    #[serde(with = "file_result::os_str_serde::vec")]
+    pub roots : Vec<PathBuf>,
+
+    // Whether this is an "archive" or a "log".
+    pub kind : String,
+
+    // When the file was written.
+    pub created : DateTime<Utc>,
+
+    // Whether the paths in the file are relative to the root they were found under (see
+    // relative_paths in Config) instead of absolute.
+    #[serde(default)]
+    pub relative_paths : bool,
+}
+
+// The header is written wrapped in an object with a single duff_header key, so it can never be
+// mistaken for a FileResult record.
+#[derive(Serialize, Deserialize)]
+struct HeaderRecord {
+    duff_header : Header,
+}
+
+impl Header {
+
+    // Create a header for a file of the given kind ("archive" or "log") written by this version of
+    // DuFF on this host.
+    pub fn new(kind: &str, roots: Vec<PathBuf>) -> Header {
+        Header {
+            format_version : FORMAT_VERSION,
+            duff_version : util::PROG_VERS.to_string(),
+            hash_algorithm : file_result::HASH_ALGORITHM.to_string(),
+            host : util::host_name(),
+            roots : roots,
+            kind : kind.to_string(),
+            created : Utc::now(),
+            relative_paths : false,
+        }
+    }
+
+    // Create a header for a file of the given kind for the run described by curr_conf. Only
+    // archives are ever written with relative paths.
+    pub fn from_conf(kind: &str, curr_conf: &Config) -> Header {
+        let mut header = Header::new(kind, util::canon_roots(curr_conf));
+        header.relative_paths = (kind == "archive") && curr_conf.relative_paths;
+
+        header
+    }
+
+    // The header as the single line we write at the top of a file.
+    pub fn to_line(&self) -> String {
+        // The header is just strings and numbers, so it will always serialize.
+        serde_json::to_string(&HeaderRecord {duff_header: self.clone()}).unwrap()
+    }
+}
+
+// The read_records function reads in the archive or log file at rec_str, returning its header and
+// all of the FileResult records in it. Parsing is strict, if we can't open the file or any line
+// can't be understood we let the user know exactly where and exit, rather than silently dropping
+// records. Files without a header are read as format version 1, and records from older versions
+// are brought up to date with migrate_record.
+// Arguments are as follows:
+// rec_str: Path of the archive or log file to read.
+pub fn read_records(rec_str: &str) -> (Header, Vec<FileResult>) {
+    let rec_file = match File::open(rec_str) {
+        Ok(t) => t,
+        Err(e) => {
+
+            // If we can't read the file kill the program
+            eprintln!("[Error reading archive file] {}", e);
+            println!("Error reading input archive file {}. \nPlease fix and try to re-run \
+                    or re-run without using this file.", rec_str);
+            exit(1)
+        }
+    };
+
+    let mut header: Option<Header> = None;
+    let mut entries = Vec::new();
+
+    for (line_num, line) in (1..).zip(BufReader::new(rec_file).lines()) {
+        let curr_line = match line {
+            Ok(t) => t,
+            Err(e) => parse_error(rec_str, line_num, &e.to_string())
+        };
+
+        // Skip comments and blank lines
+        if curr_line.starts_with('#') || curr_line.trim().is_empty() {
+            continue
+        }
+
+        let curr_val: Value = match serde_json::from_str(&curr_line) {
+            Ok(t) => t,
+            Err(e) => parse_error(rec_str, line_num, &e.to_string())
+        };
+
+        // The header has to come before any records.
+        if curr_val.get("duff_header").is_some() {
+            if header.is_some() || !entries.is_empty() {
+                parse_error(rec_str, line_num, "header found after the start of the file");
+            }
+
+            let curr_header: HeaderRecord = match serde_json::from_value(curr_val) {
+                Ok(t) => t,
+                Err(e) => parse_error(rec_str, line_num, &e.to_string())
+            };
+
+            if curr_header.duff_header.format_version > FORMAT_VERSION {
+                parse_error(rec_str, line_num, &format!(
+                    "written in format version {} by DuFF v{}, but this DuFF only understands up \
+                    to format version {}", curr_header.duff_header.format_version,
+                    curr_header.duff_header.duff_version, FORMAT_VERSION));
+            }
+
+            header = Some(curr_header.duff_header);
+            continue
+        }
+
+        // No header by the time we see the first record means this predates headers.
+        if header.is_none() {
+            let mut legacy = Header::new("archive", Vec::new());
+            legacy.format_version = 1;
+            legacy.duff_version = String::from("unknown");
+            legacy.host = String::from("unknown");
+            header = Some(legacy);
+        }
+
+        let version = header.as_ref().map_or(1, |x| x.format_version);
+
+        match serde_json::from_value(migrate_record(version, curr_val)) {
+            Ok(t) => entries.push(t),
+            Err(e) => parse_error(rec_str, line_num, &e.to_string())
+        }
+    }
+
+    // An empty file is an empty archive.
+    let header = header.unwrap_or_else(|| Header::new("archive", Vec::new()));
+
+    (header, entries)
+}
+
+// The read_archive function reads in the archive at arch_str for its hashes, returning the
+// FileResult records that have one. Hashes calculated with a different algorithm are useless to us,
+// so we refuse those files outright. The user's path maps are applied to every path, so archives
+// written where the storage is mounted somewhere else still line up with our paths. Relative paths
+// are resolved against the roots in the archive's header (path mapped the same way), and only if
+// that root is one of this run's directories, as we can't tell which other tree (if any) it is and
+// guessing wrong would hand a file another file's hash.
+// Arguments are as follows:
+// arch_str: Path of the archive file to read.
+// curr_conf: The DuFF config, holding the path maps and directories for this run.
+pub fn read_archive(arch_str: &str, curr_conf: &Config) -> Vec<FileResult> {
+    let (header, entries) = read_records(arch_str);
+    check_algorithm(arch_str, &header);
+
+    let curr_roots = util::canon_roots(curr_conf);
+    let roots: Vec<PathBuf> = header.roots.iter()
+        .map(|x| util::map_path(x, &curr_conf.path_map))
+        .collect();
+
+    let entries = entries.into_iter()
+        .filter(|x| !x.hash.is_empty())
+        .filter(|x| x.file_path.is_absolute() ||
+            entry_root(x, &roots).is_some_and(|y| curr_roots.contains(y)))
+        .map(|mut x| {
+            x.file_path = util::map_path(&x.file_path, &curr_conf.path_map);
+            x.dir_path = util::map_path(&x.dir_path, &curr_conf.path_map);
+            x
+        })
+        .collect();
+
+    resolve_relative(&roots, entries)
+}
+
+// The comment line written to a log once the file search is finished, a log without it came from a
+// run that was interrupted while still looking for files.
+pub const SEARCH_DONE: &str = "# Finished file search";
+
+// And the one written in its place when the run was interrupted first, for anyone reading the log.
+pub const SEARCH_STOPPED: &str = "# Interrupted before the file search finished, this log can't be \
+                                  resumed";
+
+// The read_log function reads in the log at log_str from a previous run so it can be resumed,
+// returning the paths of the files that run examined and the records of the files it had hashed,
+// with the user's path maps applied. Only logs from runs that finished searching for files can be
+// resumed, otherwise we would silently miss files.
+// Arguments are as follows:
+// log_str: Path of the log file to read.
+// curr_conf: The DuFF config, holding the path maps for this run.
+pub fn read_log(log_str: &str, curr_conf: &Config) -> (Vec<PathBuf>, Vec<FileResult>) {
+    let (header, entries) = read_records(log_str);
+
+    if header.kind != "log" {
+        eprintln!("{} is a DuFF {}, not a log, so it can't be resumed.", log_str, header.kind);
+        exit(1)
+    }
+
+    check_algorithm(log_str, &header);
+
+    // read_records skips comments, so look for the end of the search ourselves.
+    let search_done = match fs::read_to_string(log_str) {
+        Ok(t) => t.lines().any(|x| x == SEARCH_DONE),
+        Err(_) => false
+    };
+
+    if !search_done {
+        eprintln!("{} is from a run that stopped before it finished searching for files, please \
+                  start a new run instead.", log_str);
+        exit(1)
+    }
+
+    let mut examined: Vec<PathBuf> = Vec::new();
+    let mut hashed: Vec<FileResult> = Vec::new();
+
+    for mut x in entries {
+        x.file_path = util::map_path(&x.file_path, &curr_conf.path_map);
+        x.dir_path = util::map_path(&x.dir_path, &curr_conf.path_map);
+
+        if x.hash.is_empty() {
+            examined.push(x.file_path);
+        } else {
+            hashed.push(x);
+        }
+    }
+
+    (examined, hashed)
+}
+
+// The migrate_record function brings a single record written in format version `version` up to
+// date with the current FileResult. So far it has nothing to do: version 1 (no header) records
+// only lack fields FileResult fills in itself when missing (dev, inode and ctime default to 0,
+// file_type to empty), version 2 records never hold the {"hex": "..."} paths version 3 added for
+// names that aren't valid UTF-8, and version 3 relative records just lack the root version 4 added
+// (see resolve_relative), so every older record reads as it is. Changes to FileResult
+// that older records can't be read as should be handled here, one version at a time.
+// Arguments are as follows:
+// _version: The format version the record was written in.
+// record: The record as parsed JSON.
+fn migrate_record(_version: u32, record: Value) -> Value {
+    record
+}
+
+// The parse_error function lets the user know exactly where in which file we couldn't make sense
+// of things, and exits.
+fn parse_error(rec_str: &str, line_num: usize, err_str: &str) -> ! {
+    eprintln!("Error reading {} at line {}: {}", rec_str, line_num, err_str);
+    exit(1)
+}
+
+// The write_archive function writes entries out to arch_str as an archive, with the supplied
+// header. Everything is written to a temporary file next to arch_str first and then renamed into
+// place, so we never leave a half written archive behind (which matters when we are overwriting the
+// input archive).
+// Arguments are as follows:
+// arch_str: Path of the archive file to write.
+// header: The header to write at the top of the archive.
+// entries: The FileResult entries to write out.
+pub fn write_archive(arch_str: &str, header: &Header, entries: &[FileResult]) {
+    let tmp_str = format!("{}.{}.tmp", arch_str, std::process::id());
+
+    let write_res = File::create(&tmp_str)
+        .and_then(|f| {
+            let mut writer = BufWriter::new(f);
+
+            writeln!(writer, "{}", header.to_line())?;
+
+            for x in entries.iter() {
+                // FileResults are just strings and numbers, so they will always serialize.
+                writeln!(writer, "{}", serde_json::to_string(x).unwrap())?;
+            }
+
+            writer.flush()
+        })
+        .and_then(|_| fs::rename(&tmp_str, arch_str));
+
+    if let Err(e) = write_res {
+        let _ = fs::remove_file(&tmp_str);
+
+        eprintln!("[Error writing archive file] {}", e);
+        println!("Error writing archive file {}.", arch_str);
+        exit(1)
+    }
+}
+
+// The merge function combines several archives into a single archive. When the same file shows up
+// in more than one archive the entry with the newest mtime wins, ties going to the archive listed
+// last.
+fn merge(in_args: &ArgMatches) {
+
+    // Both of these are required, so clap will have given us something.
+    let in_archs: Vec<&str> = in_args.values_of("archives").unwrap().collect();
+    let out_arch = in_args.value_of("out").unwrap();
+
+    let mut merged: HashMap<(Option<usize>, PathBuf), FileResult> = HashMap::new();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    let mut n_read = 0;
+
+    let mut relative_paths = None;
+
+    for x in in_archs.iter() {
+        let (header, entries) = read_records(x);
+        check_algorithm(x, &header);
+
+        // Relative and absolute paths can't be told apart once merged, so don't mix them.
+        if *relative_paths.get_or_insert(header.relative_paths) != header.relative_paths {
+            eprintln!("Archive file {} has {} paths, but the archives before it do not.", x,
+                      if header.relative_paths { "relative" } else { "absolute" });
+            exit(1)
+        }
+
+        for y in header.roots.iter() {
+            if !roots.contains(y) {
+                roots.push(y.clone());
+            }
+        }
+
+        for mut y in entries {
+            n_read += 1;
+
+            // Relative entries point at their root by position, which moves in the merged roots.
+            y.root = y.root.and_then(|i| header.roots.get(i))
+                .and_then(|t| roots.iter().position(|r| r == t));
+
+            let key = (y.root, y.file_path.clone());
+
+            let keep_new = match merged.get(&key) {
+                Some(t) => (y.mtime >= t.mtime),
+                None => true
+            };
+
+            if keep_new {
+                merged.insert(key, y);
+            }
+        }
+    }
+
+    let mut entries: Vec<FileResult> = merged.into_values().collect();
+    entries.sort_by(|a, b| (a.root, &a.file_path).cmp(&(b.root, &b.file_path)));
+
+    let mut header = Header::new("archive", roots);
+    header.relative_paths = relative_paths.unwrap_or(false);
+
+    write_archive(out_arch, &header, &entries);
+
+    println!("Merged {} entries from {} archives into {} entries [{}].", n_read, in_archs.len(),
+             entries.len(), out_arch);
+}
+
+// The prune function drops entries from an archive whose files no longer exist, or whose size or
+// mtime no longer match what was archived (so the archived hash can't be trusted anymore). The
+// pruned archive overwrites the input archive unless an output archive is given.
+fn prune(in_args: &ArgMatches) {
+    let in_arch = in_args.value_of("archive").unwrap();
+    let out_arch = in_args.value_of("out").unwrap_or(in_arch);
+
+    let (header, entries) = read_records(in_arch);
+    let n_read = entries.len();
+
+    // Entries with relative paths are kept if they are still current under their root.
+    let entries: Vec<FileResult> = entries.into_iter()
+        .filter(|x| resolve_relative(&header.roots, vec![x.clone()]).iter().any(still_current))
+        .collect();
+
+    // Pruning doesn't re-hash anything, so the hashes are still whatever algorithm made them.
+    let mut new_header = Header::new(&header.kind, header.roots);
+    new_header.hash_algorithm = header.hash_algorithm;
+    new_header.relative_paths = header.relative_paths;
+
+    write_archive(out_arch, &new_header, &entries);
+
+    println!("Pruned {} of {} entries [{}].", n_read - entries.len(), n_read, out_arch);
+}
+
+// The verify function re-hashes the files in an archive (or an evenly spaced sample of them) and
+// reports any file whose content no longer matches its archived hash even though its size and mtime
+// are unchanged, i.e. bit rot. Files that changed or disappeared are counted but not re-hashed.
+// Files that can't be read back (they vanished mid-check, or lost their permissions) are reported
+// as unreadable, as we can't vouch for them. We exit with a non-zero status if any mismatches or
+// unreadable files were found.
+fn verify(in_args: &ArgMatches) {
+    let in_arch = in_args.value_of("archive").unwrap();
+
+    let mut jobs = 1;
+
+    if let Some(n_jobs) = in_args.value_of("jobs") {
+        match n_jobs.parse::<usize>() {
+            Ok(n) => jobs = n,
+            Err(_e) => {
+                eprintln!("Number of jobs specificed, {}, is not a valid number!", n_jobs);
+                exit(1);
+            }
+        }
+    }
+
+    let (header, entries) = read_records(in_arch);
+    check_algorithm(in_arch, &header);
+
+    let entries: Vec<FileResult> = resolve_relative(&header.roots, entries).into_iter()
+        .filter(|x| !x.hash.is_empty())
+        .collect();
+    let n_total = entries.len();
+
+    // Work out which entries we are checking, every entry unless the user asked for a sample.
+    let mut sample: Vec<FileResult> = match in_args.value_of("sample") {
+        Some(n_sample) => {
+            let n_sample = match n_sample.parse::<usize>() {
+                Ok(n) => n,
+                Err(_e) => {
+                    eprintln!("Sample size specified, {}, is not a valid number!", n_sample);
+                    exit(1);
+                }
+            };
+
+            let step = std::cmp::max(1, n_total / std::cmp::max(1, n_sample));
+            entries.into_iter().step_by(step).take(n_sample).collect()
+        },
+        None => entries
+    };
+
+    // Anything that has been modified or removed can't tell us about bit rot.
+    let n_checked = sample.len();
+    sample.retain(still_current);
+    let n_changed = n_checked - sample.len();
+
+    rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global().unwrap();
+
+    // Re-hash a copy of each entry, holding on to the error for any we couldn't.
+    let rehashed: Vec<Result<String, String>> = sample.par_iter()
+        .map(|x| {
+            let mut curr_fr = x.clone();
+
+            match curr_fr.calc_hash(524288) {
+                Ok(_) => Ok(curr_fr.hash),
+                Err(e) => Err(e.to_string())
+            }
+        })
+        .collect();
+
+    let mut n_rot = 0;
+    let mut n_unread = 0;
+
+    for (x, y) in sample.iter().zip(rehashed.iter()) {
+        match y {
+            Ok(t) if *t != x.hash => {
+                n_rot += 1;
+                println!("MISMATCH\t{}\t{}\t{}", util::escape_path(x.file_path.as_os_str()),
+                         x.hash, t);
+            }
+            Err(e) => {
+                n_unread += 1;
+                println!("UNREADABLE\t{}\t{}", util::escape_path(x.file_path.as_os_str()), e);
+            }
+            _ => ()
+        }
+    }
+
+    println!("Verified {} of {} entries: {} changed or missing, {} ok, {} mismatched, {} \
+              unreadable.", n_checked, n_total, n_changed, sample.len() - n_rot - n_unread, n_rot,
+             n_unread);
+
+    if (n_rot > 0) || (n_unread > 0) {
+        exit(1);
+    }
+}
+
+// The stats function prints a summary of what is in an archive.
+fn stats(in_args: &ArgMatches) {
+    let in_arch = in_args.value_of("archive").unwrap();
+
+    let (header, entries) = read_records(in_arch);
+
+    let mut paths: HashMap<&Path, usize> = HashMap::new();
+    let mut groups: HashMap<String, usize> = HashMap::new();
+    let mut total_size: u128 = 0;
+    let mut n_no_hash = 0;
+    let mut oldest: Option<DateTime<Utc>> = None;
+    let mut newest: Option<DateTime<Utc>> = None;
+
+    for x in entries.iter() {
+        *paths.entry(&x.file_path).or_insert(0) += 1;
+        total_size += x.size;
+
+        if x.hash.is_empty() {
+            n_no_hash += 1;
+        } else {
+            *groups.entry(format!("{}_{}", x.size, x.hash)).or_insert(0) += 1;
+        }
+
+        oldest = Some(oldest.map_or(x.mtime, |t| std::cmp::min(t, x.mtime)));
+        newest = Some(newest.map_or(x.mtime, |t| std::cmp::max(t, x.mtime)));
+    }
+
+    let n_dupe_groups = groups.values().filter(|x| **x > 1).count();
+    let n_dupes: usize = groups.values().filter(|x| **x > 1).sum();
+
+    let border_str = "=".repeat(textwrap::termwidth());
+    let mut out_str = format!("{}\n", border_str);
+    out_str.push_str(format!("{:<21} {:^39} {:>0}\n", util::dt(), "Archive Stats",
+                             util::PROG_NAME.to_owned() + " v" + util::PROG_VERS).as_str());
+    out_str.push_str(format!("{}\n", border_str).as_str());
+
+    out_str.push_str(format!("{:<40} {:>1}\n", "Archive:", in_arch).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Format Version:", header.format_version).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Written By:",
+                             format!("DuFF v{} on {}", header.duff_version, header.host)).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Hash Algorithm:", header.hash_algorithm).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Search Directories:", header.roots.iter()
+        .map(|x| util::escape_path(x.as_os_str())).collect::<Vec<String>>().join(",")).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Relative Paths:", header.relative_paths).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Entries:", entries.len()).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Unique Paths:", paths.len()).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Entries Without Hash:", n_no_hash).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Total Size:", converter::convert(total_size as f64))
+        .as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Duplicate Groups:", n_dupe_groups).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Files In Duplicate Groups:", n_dupes).as_str());
+
+    if let (Some(o), Some(n)) = (oldest, newest) {
+        out_str.push_str(format!("{:<40} {:>1}\n", "Oldest Modified Time:", o).as_str());
+        out_str.push_str(format!("{:<40} {:>1}\n", "Newest Modified Time:", n).as_str());
+    }
+
+    print!("{}", out_str);
+}
+
+// The migrate function rewrites an archive or log in the current format version, with a fresh
+// header, so it no longer needs migrating every time it is read. The migrated file overwrites the
+// input unless an output file is given.
+fn migrate(in_args: &ArgMatches) {
+    let in_arch = in_args.value_of("archive").unwrap();
+    let out_arch = in_args.value_of("out").unwrap_or(in_arch);
+
+    let (header, entries) = read_records(in_arch);
+    let old_version = header.format_version;
+
+    let mut new_header = Header::new(&header.kind, header.roots);
+    new_header.hash_algorithm = header.hash_algorithm;
+    new_header.relative_paths = header.relative_paths;
+
+    write_archive(out_arch, &new_header, &entries);
+
+    println!("Migrated {} entries from format version {} to {} [{}].", entries.len(), old_version,
+             FORMAT_VERSION, out_arch);
+}
+
+// The resolve_relative function turns entries with paths relative to a root back into absolute
+// ones, each against the root (by its position in roots) it was found under. Entries from format
+// version 3 archives don't say which root that was, they are only resolved if there is just the
+// one root, guessing could hand a file another file's hash. Relative entries we can't place are
+// dropped, entries that are already absolute are passed through untouched.
+// Arguments are as follows:
+// roots: The directories relative entries should be resolved against, in the order they were
+//        written in.
+// entries: The entries to resolve.
+pub fn resolve_relative(roots: &[PathBuf], entries: Vec<FileResult>) -> Vec<FileResult> {
+    let mut resolved = Vec::with_capacity(entries.len());
+
+    for mut x in entries {
+        if x.file_path.is_absolute() {
+            resolved.push(x);
+            continue
+        }
+
+        if let Some(y) = entry_root(&x, roots) {
+            x.file_path = y.join(&x.file_path);
+            x.dir_path = y.join(&x.dir_path);
+            x.root = None;
+            resolved.push(x);
+        }
+    }
+
+    resolved
+}
+
+// The entry_root function returns the root (out of roots) the relative entry curr_fr was found
+// under, see resolve_relative, or None if we can't tell.
+fn entry_root<'a>(curr_fr: &FileResult, roots: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    match curr_fr.root {
+        Some(i) => roots.get(i),
+        None if roots.len() == 1 => roots.first(),
+        None => None
+    }
+}
+
+// The check_algorithm function makes sure the hashes in the file described by header were made
+// with the same algorithm as ours, letting the user know and exiting if not.
+pub fn check_algorithm(arch_str: &str, header: &Header) {
+    if header.hash_algorithm != file_result::HASH_ALGORITHM {
+        eprintln!("Archive file {} holds {} hashes, but DuFF uses {} hashes.", arch_str,
+                  header.hash_algorithm, file_result::HASH_ALGORITHM);
+        exit(1)
+    }
+}
+
+// The still_current function checks whether the file an archive entry describes still exists with
+// the same size and mtime, returning a bool indicating the result.
+fn still_current(curr_fr: &FileResult) -> bool {
+    let curr_meta = match curr_fr.file_path.metadata() {
+        Ok(t) => t,
+        Err(_) => return false
+    };
+
+    let mtime: DateTime<Utc> = match curr_meta.modified() {
+        Ok(t) => t.into(),
+        Err(_) => return false
+    };
+
+    ((u128::from(curr_meta.len()) == curr_fr.size) && (mtime == curr_fr.mtime))
+}
@@ -0,0 +1,210 @@
+// This file/module holds the persistent hash cache. Every hash DuFF calculates gets stored in a
+// cache file (by default in ~/.cache/duff) keyed by the file's device, inode, size, mtime and
+// ctime, so a later run over a mostly unchanged tree can skip re-hashing anything that hasn't been
+// touched.
+
+// For the cache location and the user's requested cache size
+use crate::config::Config;
+use crate::file_result::FileResult;
+
+// The cache is held in memory as a hashmap and read from/written to disk as JSON lines.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+// For keeping track of when an entry was last used, which decides what gets evicted.
+use chrono::Utc;
+use std::cmp::Reverse;
+
+// For serializing and deserializing cache entries
+use serde::{Serialize, Deserialize};
+
+
+// Everything that has to match for us to trust a cached hash. If any of these change the file has
+// (or at least may have) been modified since it was hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    dev : u64,
+    inode : u64,
+    size : u128,
+    mtime : i64,
+    ctime : i64,
+}
+
+// A single line of the cache file, the key fields, the hash and when it was last used (seconds
+// since the epoch).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    dev : u64,
+    inode : u64,
+    size : u128,
+    mtime : i64,
+    ctime : i64,
+    hash : String,
+    last_used : i64,
+}
+
+impl CacheEntry {
+    fn key(&self) -> CacheKey {
+        CacheKey {dev: self.dev, inode: self.inode, size: self.size, mtime: self.mtime,
+                  ctime: self.ctime}
+    }
+}
+
+// The HashCache struct holds the cache entries in memory along with where they should be written
+// back to and how many of them we are allowed to keep.
+pub struct HashCache {
+
+    // Whether we are using the cache at all, if not every function here is a no-op.
+    enabled : bool,
+
+    // The directory the cache file lives in, and the cache file itself.
+    cache_dir : PathBuf,
+    cache_file : PathBuf,
+
+    // The most entries we will write back out, the least recently used ones are evicted first.
+    max_entries : usize,
+
+    // The entries themselves.
+    entries : HashMap<CacheKey, CacheEntry>,
+}
+
+impl HashCache {
+
+    // The load function reads in the cache file from the user's cache directory, if the user hasn't
+    // turned the cache off. A missing cache file just means an empty cache, and lines we can't make
+    // sense of are skipped, as the worst that can happen is we re-hash a file.
+    pub fn load(curr_conf: &Config) -> HashCache {
+        let mut cache = HashCache {
+            enabled : curr_conf.use_cache,
+            cache_dir : PathBuf::from(&curr_conf.cache_dir),
+            cache_file : PathBuf::from(&curr_conf.cache_dir).join("hash_cache.jsonl"),
+            max_entries : curr_conf.cache_size as usize,
+            entries : HashMap::new(),
+        };
+
+        if !cache.enabled {
+            return cache;
+        }
+
+        let cache_reader = match File::open(&cache.cache_file) {
+            Ok(t) => BufReader::new(t),
+            Err(_) => return cache,
+        };
+
+        for line in cache_reader.lines() {
+            let curr_line = match line {
+                Ok(t) => t,
+                Err(_) => continue
+            };
+
+            let curr_entry: CacheEntry = match serde_json::from_str(&curr_line) {
+                Ok(t) => t,
+                Err(_) => continue
+            };
+
+            cache.entries.insert(curr_entry.key(), curr_entry);
+        }
+
+        cache
+    }
+
+    // The get function looks up the hash for the file represented by curr_fr, returning it wrapped
+    // in a Some if we have it cached, otherwise None.
+    pub fn get(&self, curr_fr: &FileResult) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let key = cache_key(curr_fr)?;
+
+        self.entries.get(&key).map(|x| x.hash.clone())
+    }
+
+    // The update function stores (or refreshes) the hash of the file represented by curr_fr, which
+    // needs to already have its hash calculated.
+    pub fn update(&mut self, curr_fr: &FileResult) {
+        if !self.enabled || curr_fr.hash.is_empty() {
+            return;
+        }
+
+        let key = match cache_key(curr_fr) {
+            Some(t) => t,
+            None => return
+        };
+
+        let entry = CacheEntry {dev: key.dev, inode: key.inode, size: key.size, mtime: key.mtime,
+                                ctime: key.ctime, hash: curr_fr.hash.clone(),
+                                last_used: Utc::now().timestamp()};
+
+        self.entries.insert(key, entry);
+    }
+
+    // The save function writes the cache back out to disk, evicting the least recently used
+    // entries if we have more than max_entries. We write to a temporary file first and rename it
+    // into place, so a crash (or another DuFF run saving at the same time) can't leave a torn cache
+    // file behind. Failing to save the cache is not worth killing the run over, so we just let the
+    // user know.
+    pub fn save(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut entries: Vec<&CacheEntry> = self.entries.values().collect();
+
+        if entries.len() > self.max_entries {
+            entries.sort_by_key(|x| Reverse(x.last_used));
+            entries.truncate(self.max_entries);
+        }
+
+        let tmp_file = self.cache_file.with_extension(format!("jsonl.{}.tmp", std::process::id()));
+
+        let save_res = fs::create_dir_all(&self.cache_dir)
+            .and_then(|_| File::create(&tmp_file))
+            .and_then(|f| {
+                let mut writer = BufWriter::new(f);
+
+                for x in entries.iter() {
+                    // Our entries are just numbers and strings, so they will always serialize.
+                    writeln!(writer, "{}", serde_json::to_string(x).unwrap())?;
+                }
+
+                writer.flush()
+            })
+            .and_then(|_| fs::rename(&tmp_file, &self.cache_file));
+
+        if let Err(e) = save_res {
+            eprintln!("Error saving hash cache to {}.\nError text: {}",
+                      self.cache_file.display(), e);
+            let _ = fs::remove_file(&tmp_file);
+        }
+    }
+}
+
+// The cache_key function builds the key for the file represented by curr_fr, returning None for
+// files we don't have a device and inode for, as we can't safely identify those.
+fn cache_key(curr_fr: &FileResult) -> Option<CacheKey> {
+    if (curr_fr.dev == 0) && (curr_fr.inode == 0) {
+        return None;
+    }
+
+    Some(CacheKey {dev: curr_fr.dev, inode: curr_fr.inode, size: curr_fr.size,
+                   mtime: curr_fr.mtime.timestamp_nanos(), ctime: curr_fr.ctime})
+}
+
+// The default_cache_dir function works out where the cache should live if the user didn't tell us,
+// following the XDG convention of $XDG_CACHE_HOME/duff, falling back on ~/.cache/duff. If neither
+// is set we return None and the cache is switched off.
+pub fn default_cache_dir() -> Option<String> {
+    if let Ok(t) = std::env::var("XDG_CACHE_HOME") {
+        if !t.is_empty() {
+            return Some(format!("{}/duff", t));
+        }
+    }
+
+    match std::env::var("HOME") {
+        Ok(t) if !t.is_empty() => Some(format!("{}/.cache/duff", t)),
+        _ => None
+    }
+}
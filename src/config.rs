@@ -1,22 +1,46 @@
 // For some constants and datetime functions
 use crate::util;
 
+// For the default location of the hash cache
+use crate::cache;
+
+// For the file types the user can filter on
+use crate::file_type;
+
 // process: To exit when there are errors
 // env: To grab the current working directory, if needed
 // fs: To complete simple checks on input search directories.
 // fmt: Display trait implementation
 use std::{process, env, fs, fmt};
 
+// For the search and reference directories, which don't have to be valid UTF-8
+use std::path::{Path, PathBuf};
+
 // Allows for reading in more human friendly values for lower and upper limits
 use byte_unit::Byte;
 
 // Allows for more human friendly printing of byte values for lower and upper limits.
 use pretty_bytes::converter;
 
+// For the mtime window (newer_than and older_than)
+use chrono::{DateTime, Utc};
+
 // clap makes all of this work, but more specificly needed here as Config::new takes an ArgMatches
 // value as it's only argument.
 use clap::ArgMatches;
 
+// The command line arguments get merged with anything from the user's config files
+use crate::config_file::ArgSource;
+
+// The built in extension groups, used with -e @NAME (or --exclude-ext @NAME). Config files can add
+// their own, or replace these, in an [ext_groups] table.
+const EXT_GROUPS: &[(&str, &[&str])] = &[
+    ("sequencing", &["fastq", "fq", "fastq.gz", "fq.gz", "bam", "bai", "cram", "crai", "sam"]),
+    ("images", &["jpg", "jpeg", "png", "gif", "tif", "tiff", "bmp", "svg", "heic"]),
+    ("archives", &["tar", "tar.gz", "tgz", "tar.bz2", "tar.xz", "zip", "gz", "bz2", "xz", "7z"]),
+    ("documents", &["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "txt"]),
+];
+
 // The Config struct is simply here to contain the user input that is obtained using clap.
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -28,8 +52,8 @@ pub struct Config {
 
     // The directories the user requested we search for duplicate files within. The user will give
     // DuFF a comma separated list of directories, but internally we simply store that as a vector
-    // of Strings.
-    pub search_path: Vec<String>,
+    // of paths.
+    pub search_path: Vec<PathBuf>,
 
 
     // Optional flags:
@@ -54,6 +78,43 @@ pub struct Config {
     // stderr to /dev/null
     pub hide_err : bool,
 
+    // The src_dupes flag only matters when comparing against reference directories (ref_path). By
+    // default only groups with at least one source and one reference file are reported, with this
+    // flag on we also report groups that are made up of only source files.
+    pub src_dupes : bool,
+
+    // The unique flag turns DuFF around to look for files whose content has no copies anywhere else
+    // in the search directories, reporting those instead of the duplicates.
+    pub unique : bool,
+
+    // The empty flag tells DuFF to report the empty (zero length) files it finds as one group, and
+    // the empty directories as another, rather than skipping them.
+    pub empty : bool,
+
+    // The use_cache flag tells DuFF to look up hashes in (and save new hashes to) the persistent
+    // hash cache. It is on by default, the user can switch it off with the no_cache flag.
+    pub use_cache : bool,
+
+    // relative_paths tells DuFF to write the paths in the archive relative to the search (or
+    // reference) directory each file was found under, so the archive can be used wherever the
+    // directories are mounted.
+    pub relative_paths : bool,
+
+    // canonicalize tells DuFF to canonicalize the path of every file it examines, resolving any
+    // symlinks followed along the way. This costs several syscalls per file, so by default only the
+    // search and reference directories are canonicalized and file paths are just made absolute.
+    pub canonicalize : bool,
+
+    // sort_output tells DuFF to search directories in name order and write the report's groups in
+    // a set order, so runs over the same files give the same report. It is on by default, the
+    // user can switch it off with the unsorted flag.
+    pub sort_output : bool,
+
+    // detect_types tells DuFF to work out each candidate file's type from its contents (see
+    // file_type.rs), recording it in the log, archive and report. It is switched on by the
+    // detect_types flag or by asking for particular types (file_types).
+    pub detect_types : bool,
+
 
     // Optional Arguments:
 
@@ -80,6 +141,43 @@ pub struct Config {
     // no user requested extension filtering to worry about.
     pub exts : Vec<String>,
 
+    // exclude_exts holds the extensions files must not have, stored the same way as exts (with
+    // any @groups expanded), empty if the user didn't give any.
+    pub exclude_exts : Vec<String>,
+
+    // newer_than and older_than hold the window the user wants file mtimes to fall in (see
+    // --newer-than and --older-than), None for an open end. Ages are turned into a point in time
+    // counted back from the start of the run.
+    pub newer_than : Option<DateTime<Utc>>,
+    pub older_than : Option<DateTime<Utc>>,
+
+    // owner_uids holds the user ids (from --user and --uid) and owner_gids the group ids (from
+    // --group) the user wants files to be owned by, an empty list means anyone.
+    pub owner_uids : Vec<u32>,
+    pub owner_gids : Vec<u32>,
+
+    // perm holds the permission filter from --perm, how the bits should be matched ('=' exactly,
+    // '-' all of them, '/' any of them) and the bits themselves.
+    pub perm : Option<(char, u32)>,
+
+    // file_types holds the detected types (see file_type::TYPES) the user wants files limited to,
+    // empty if any type will do.
+    pub file_types : Vec<String>,
+
+    // min_depth and max_depth limit how deep into each search directory we look, counting the
+    // entries directly inside a search directory as depth 1 (like find). Files shallower than
+    // min_depth aren't examined and nothing deeper than max_depth is looked at, None meaning no
+    // limit.
+    pub min_depth : usize,
+    pub max_depth : Option<usize>,
+
+    // skip_hidden tells DuFF to skip files and directories whose names start with a dot.
+    pub skip_hidden : bool,
+
+    // ignore_files tells DuFF to honour any .gitignore, .ignore and .duffignore files it finds
+    // while searching, skipping whatever they match.
+    pub ignore_files : bool,
+
     // out_dir will hold the directory the user wants us to write files to, defaulting to the
     // current working directory.  If we cannot write to out_dir, the program will fail, letting the
     // user know the reason.
@@ -93,6 +191,53 @@ pub struct Config {
     // is the file that gets generated running DuFF with the archive flag on.
     pub prev_hash_file : String,
 
+    // hash_match holds how files are matched against the hashes in prev_hash_file, one of "path"
+    // (the default, same path and mtime), "inode" (same inode and mtime) or "name" (same file name
+    // and mtime). The last two let moved or renamed directories reuse their hashes. Sizes always
+    // have to match.
+    pub hash_match : String,
+
+    // path_map holds the (OLD, NEW) path prefix pairs the user gave us as OLD=NEW, applied to the
+    // paths in previous hash files so ones written where the storage is mounted somewhere else
+    // (/mnt/lab vs /data/lab) still match.
+    pub path_map : Vec<(String, String)>,
+
+    // ref_path holds the reference directories the user wants the search directories compared
+    // against, i.e. a canonical archive that files in search_path may already be stored in. Like
+    // search_path the user gives us a comma separated list, but we store the canonicalized version
+    // of each directory so we can tell which set a file belongs to just by looking at its path.
+    pub ref_path: Vec<PathBuf>,
+
+    // unique_root will hold the canonicalized directory the user wants the unique files restricted
+    // to. Files everywhere are still compared, but only unique files inside it are reported.
+    pub unique_root : PathBuf,
+
+    // target_ls holds the files the user wants us to find copies of, given to us as a comma
+    // separated list, stored canonicalized. When set, only files matching a target's size are
+    // hashed and the report lists every location each target's content appears at.
+    pub target_ls : Vec<PathBuf>,
+
+    // files_from will hold the path to a list of files (newline or NUL separated) the user wants
+    // examined directly, skipping directory traversal for them. A "-" means read the list from stdin.
+    pub files_from : String,
+
+    // dirs_from will hold the path to a list of directories (newline or NUL separated) to search,
+    // on top of any given with -d. A "-" means read the list from stdin.
+    pub dirs_from : String,
+
+    // cache_dir will hold the directory the hash cache is kept in, defaulting to
+    // $XDG_CACHE_HOME/duff or ~/.cache/duff.
+    pub cache_dir : String,
+
+    // cache_size holds the maximum number of hashes to keep in the cache, defaulting to 1,000,000.
+    // Once there are more than that, the least recently used hashes are dropped.
+    pub cache_size : u64,
+
+    // config_files holds the config files we read settings from, in the order they were read, and
+    // profile the name of the profile picked from them (empty if none).
+    pub config_files : Vec<String>,
+    pub profile : String,
+
 
     // INTERNAL ARGUMENTS: Arguments not directly set by the user, but set in response to different
     //                     user input.
@@ -117,6 +262,22 @@ pub struct Config {
     // using the debug flag.
     pub resume : bool,
 
+    // The have_ref flag is set by DuFF if the user provides reference directories (using the -ref
+    // argument), switching DuFF over to comparing the search directories against them.
+    pub have_ref : bool,
+
+    // The have_unique_root flag is set by DuFF if the user restricts the reported unique files to a
+    // directory (using the -unique_under argument).
+    pub have_unique_root : bool,
+
+    // The have_target flag is set by DuFF if the user provides target files to find copies of
+    // (using the -target argument).
+    pub have_target : bool,
+
+    // The have_files_from flag is set by DuFF if the user provides a list of files to examine
+    // (using the -files_from argument).
+    pub have_files_from : bool,
+
     // The have_hash flag is set by DuFF if the user provides a hash file (using -hash argument).
     // This hash file must be generated by a previous run of DuFF in which the user specified the -a
     // flag.
@@ -130,7 +291,11 @@ pub struct Config {
 
 
 impl Config  {
-    pub fn new(in_args: ArgMatches) -> Config {
+    pub fn new(cli_args: ArgMatches) -> Config {
+
+        // Everything is read through the config files merged with the command line, the command
+        // line winning wherever both say something.
+        let in_args = ArgSource::new(&cli_args);
 
         // Initialize a bunch of placeholders that we will use to generate a new Config struct.
         // Required argument(s):
@@ -143,6 +308,14 @@ impl Config  {
         let mut hide_prog = false;
         let mut silent = false;
         let mut hide_err = false;
+        let mut src_dupes = false;
+        let mut unique = false;
+        let mut empty = false;
+        let mut use_cache = true;
+        let mut relative_paths = false;
+        let mut canonicalize = false;
+        let mut sort_output = true;
+        let mut detect_types = false;
 
         // Optional Arguments:
 
@@ -159,6 +332,21 @@ impl Config  {
 
         // Default extension is just "*".
         let mut exts: Vec<String> = vec![String::from("*")];
+        let mut exclude_exts: Vec<String> = Vec::new();
+
+        // No mtime, ownership or permission filtering by default.
+        let mut newer_than = None;
+        let mut older_than = None;
+        let mut owner_uids: Vec<u32> = Vec::new();
+        let mut owner_gids: Vec<u32> = Vec::new();
+        let mut perm = None;
+        let mut file_types: Vec<String> = Vec::new();
+
+        // No limits on how deep we search, and nothing hidden or ignored is skipped by default.
+        let mut min_depth = 0;
+        let mut max_depth = None;
+        let mut skip_hidden = false;
+        let mut ignore_files = false;
 
         // out_dir needs to be mentioned up here for the compiler to be happy.
         let mut out_dir;
@@ -167,13 +355,22 @@ impl Config  {
         // to try to open these for writing.
         let mut res_file = String::from("");
         let mut prev_hash_file = String::from("");
-
-        // INTERNAL ARGUMENTS:
-        let archive_file;
-        let log_file;
+        let mut hash_match = String::from("path");
+        let mut path_map: Vec<(String, String)> = Vec::new();
+        let mut ref_path: Vec<PathBuf> = Vec::new();
+        let mut unique_root = PathBuf::new();
+        let mut target_ls: Vec<PathBuf> = Vec::new();
+        let mut files_from = String::from("");
+        let mut dirs_from = String::from("");
+        let mut cache_dir = String::from("");
+        let mut cache_size = 1000000;
 
         // INTERNAL FLAGS:
         let mut resume = false;
+        let mut have_ref = false;
+        let mut have_unique_root = false;
+        let mut have_target = false;
+        let mut have_files_from = false;
         let mut have_hash = false;
         let mut user_set_dir= false;
 
@@ -182,44 +379,40 @@ impl Config  {
 
 
         // Required argument(s):
-        // I feel OK using unwrap here because this is a required argument, so clap will have to
-        // have received some kind of string here for DuFF to even get to this point.
-        let paths = in_args.value_of("dir").unwrap();
+        // The search directories are required unless the user hands us a list of files or a log to
+        // resume instead, in which case we just have nothing to traverse. As they can come from a
+        // config file, clap can't check this one for us.
+        let mut path_vec: Vec<PathBuf> = Vec::new();
+
+        if !in_args.is_present("dir") && !in_args.is_present("roots") &&
+            !in_args.is_present("dirs_from") && !in_args.is_present("files_from") &&
+            !in_args.is_present("resume") {
+            eprintln!("{}", textwrap::fill("Please specify the directories to search (-d, --dir), \
+                a list of files to examine (--files-from) or a log to resume (-r, --resume), either \
+                on the command line or in a config file.", textwrap::termwidth()));
+            process::exit(1);
+        }
 
-        // Split the string from clap into separate directory paths using comma delimiter.
+        // Each -d can be a single directory or a comma separated list of them, see split_os_paths.
         // Skip checking these files here as we will do that in the next step.
-        let path_vec: Vec<String> = paths.split(',').map(|s| s.to_string()).collect();
+        if let Some(paths) = in_args.values_of_os("dir") {
+            path_vec = paths.iter().flat_map(|x| util::split_os_paths(x)).collect();
+        }
 
-        // Check each input and parsed directory to make sure its accessible and is a directory.
-        for x in path_vec.iter() {
-
-            // Getting a metadata object for the path string the user provided
-            // TODO: Write check to see if a dir w/o read perms returns OK or ERR
-            // TODO: Should we exit if there is an issue with one of the dirs or just try to contin?
-            match fs::metadata(x) {
-                Ok(m) => {
-
-                    // Check to see if this is actually a directory and if it is not then send an
-                    // error to stderr and exit, if it is a directory we should be good to go.
-                    if m.is_dir() == false {
-                        let err_str = format!("Specified directory {} is not a directory!",
-                                              x);
-                        eprintln!("{}", textwrap::fill(err_str.as_str(),
-                                                       textwrap::termwidth()));
-                        process::exit(1);
-                    }
-                },
-                // If there was some unknown (to me) error then capture it and send it to stderr and
-                // exit
-                Err(e) => {
-                    let err_str = format!("There was an error with the specified directory, {}: {}!",
-                                          x, e.to_string());
-                    eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
-                    process::exit(1);
-                }
-            }
+        // Directories listed at the end of the command line are taken as they are.
+        if let Some(paths) = in_args.values_of_os("roots") {
+            path_vec.extend(paths.into_iter().map(PathBuf::from));
         }
 
+        // As are the ones in a directory list.
+        if let Some(dirs_f) = in_args.value_of("dirs_from") {
+            path_vec.extend(util::read_file_list(dirs_f));
+            dirs_from = dirs_f.to_string();
+        }
+
+        // Check each input and parsed directory to make sure its accessible and is a directory.
+        check_dirs(&path_vec);
+
 
         // Optional flags:
 
@@ -248,6 +441,38 @@ impl Config  {
 
         }
 
+        if in_args.is_present("src_dupes") {
+            src_dupes = true;
+        }
+
+        if in_args.is_present("unique") {
+            unique = true;
+        }
+
+        if in_args.is_present("empty") {
+            empty = true;
+        }
+
+        if in_args.is_present("relative_paths") {
+            relative_paths = true;
+        }
+
+        if in_args.is_present("canonicalize") {
+            canonicalize = true;
+        }
+
+        if in_args.is_present("unsorted") {
+            sort_output = false;
+        }
+
+        if in_args.is_present("detect_types") {
+            detect_types = true;
+        }
+
+        if in_args.is_present("no_cache") {
+            use_cache = false;
+        }
+
         // Optional Arguments:
 
         // Try to capture user input with byte_unit's handy string to Byte function and if byte_unit
@@ -297,15 +522,117 @@ impl Config  {
         }
 
 
-        // Read in the string the user gave us that should contain comma separated extensions they
-        // want to require for files and try to split on commas. There aren't really QC checks
-        // being done here, but what can be done?
-        if let Some(mut in_exts) = in_args.value_of("exts") {
+        // Read in the extensions the user wants to require for files, each -e can be a comma
+        // separated list of them, and any @groups get swapped out for their extensions. A * in
+        // there anywhere means any extension will do.
+        if let Some(in_exts) = in_args.values_of("exts") {
+            exts = expand_exts(&in_exts, &in_args);
 
-            // Leaving the unwrap as we wouldn't get into this block unless clap gave us something
-            // for exts above in the if let Some condition.
-            in_exts = in_args.value_of("exts").unwrap();
-            exts = in_exts.split(',').map(|s| s.to_string()).collect();
+            if exts.iter().any(|x| x == "*") {
+                exts = vec![String::from("*")];
+            }
+        }
+
+        // The extensions to skip are read in the same way.
+        if let Some(in_exts) = in_args.values_of("exclude_exts") {
+            exclude_exts = expand_exts(&in_exts, &in_args);
+        }
+
+        // Ages are counted back from the same moment for both ends of the window.
+        let now = Utc::now();
+
+        if let Some(newer) = in_args.value_of("newer_than") {
+            newer_than = Some(parse_time_arg("--newer-than", newer, now));
+        }
+
+        if let Some(older) = in_args.value_of("older_than") {
+            older_than = Some(parse_time_arg("--older-than", older, now));
+        }
+
+        // Users can only be given by name here, --uid is for accounts that no longer exist.
+        if let Some(users) = in_args.values_of("user") {
+            for x in users.iter().flat_map(|x| util::split_list(x)) {
+                match lookup_user(&x) {
+                    Some(t) => owner_uids.push(t),
+                    None => filter_error(&format!("Unknown user {} (use --uid for user ids)!", x))
+                }
+            }
+        }
+
+        if let Some(uids) = in_args.values_of("uid") {
+            for x in uids.iter().flat_map(|x| util::split_list(x)) {
+                match x.parse::<u32>() {
+                    Ok(t) => owner_uids.push(t),
+                    Err(_) => filter_error(&format!("User id {} is not a valid number!", x))
+                }
+            }
+        }
+
+        // Groups can be given by name or id.
+        if let Some(groups) = in_args.values_of("group") {
+            for x in groups.iter().flat_map(|x| util::split_list(x)) {
+                match x.parse::<u32>().ok().or_else(|| lookup_group(&x)) {
+                    Some(t) => owner_gids.push(t),
+                    None => filter_error(&format!("Unknown group {}!", x))
+                }
+            }
+        }
+
+        if let Some(perm_str) = in_args.value_of("perm") {
+            match util::parse_perm(perm_str) {
+                Some(t) => perm = Some(t),
+                None => filter_error(&format!("Permission filter {} is not an octal mode like 644, \
+                                               -022 or /022!", perm_str))
+            }
+        }
+
+        // Asking for particular types means we need to detect them.
+        if let Some(types) = in_args.values_of("file_types") {
+            for x in types.iter().flat_map(|x| util::split_list(x)) {
+                let type_str = x.to_lowercase();
+
+                if !file_type::TYPES.contains(&type_str.as_str()) {
+                    filter_error(&format!("Unknown file type {} (known types: {})!", x,
+                                          file_type::TYPES.join(", ")));
+                }
+
+                file_types.push(type_str);
+            }
+
+            detect_types = true;
+        }
+
+        if let Some(depth) = in_args.value_of("min_depth") {
+            match depth.parse::<usize>() {
+                Ok(n) => min_depth = n,
+                Err(_e) => filter_error(&format!("Minimum depth specified, {}, is not a valid \
+                                                  number!", depth))
+            }
+        }
+
+        if let Some(depth) = in_args.value_of("max_depth") {
+            match depth.parse::<usize>() {
+                Ok(n) => max_depth = Some(n),
+                Err(_e) => filter_error(&format!("Maximum depth specified, {}, is not a valid \
+                                                  number!", depth))
+            }
+        }
+
+        if max_depth.is_some_and(|x| x < min_depth) {
+            filter_error("The minimum depth can't be more than the maximum depth!");
+        }
+
+        if in_args.is_present("skip_hidden") {
+            skip_hidden = true;
+        }
+
+        if in_args.is_present("ignore_files") {
+            ignore_files = true;
+        }
+
+        if cfg!(not(unix)) && (!owner_uids.is_empty() || !owner_gids.is_empty() || perm.is_some()) {
+            filter_error("The --user, --uid, --group and --perm filters are only supported on \
+                          unix-like systems!");
         }
 
         // See if the user specified an output directory and if so capture it.
@@ -332,8 +659,7 @@ impl Config  {
             };
 
             // Stuff the cwd into a string for out_dir variable
-            // TODO: Should we trust unwrap here? We know it will be a PathBuf since its from env.
-            out_dir = String::from(cwd.to_str().unwrap());
+            out_dir = cwd.display().to_string();
         }
 
         // Determine if the output directory path has a trailing / and if so remove it.
@@ -350,8 +676,7 @@ impl Config  {
 
 
         // If they want us to resume then they need to give us a log file from a previous DuFF run,
-        // which we will just take as a string, but should probably do some checks on.
-        // TODO: Add some checks to validate this file - maybe not in this function though!
+        // which we just take as a string here, archive::read_log checks it when we load it.
         if let Some(res_f) = in_args.value_of("resume") {
             res_file = res_f.to_string();
 
@@ -369,28 +694,140 @@ impl Config  {
             have_hash = true;
         }
 
+        // Whether it came from the command line or a config file, this has been checked to be one
+        // of the modes we know about (see ArgSource::check).
+        if let Some(match_mode) = in_args.value_of("hash_match") {
+            hash_match = match_mode.to_string();
+        }
+
+        // Each path map is given as OLD=NEW, split on the first = so NEW can contain one.
+        if let Some(maps) = in_args.values_of("path_map") {
+            for x in maps {
+                match x.find('=') {
+                    Some(i) if (i > 0) && (i < x.len() - 1) => {
+                        path_map.push((x[..i].to_string(), x[i + 1..].to_string()));
+                    }
+                    _ => {
+                        let err_str = format!("Path map {} is not in the form OLD=NEW!", x);
+                        eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                        process::exit(1);
+                    }
+                }
+            }
+        }
 
-        // Other work
 
-        // Specify the paths for our working files, we'll create them later.
-        if archive {
+        // If they gave us reference directories then we are comparing the search directories against
+        // them. These get the same checks as the search directories, but we hold on to their
+        // canonicalized paths as the file paths we collect later on are canonicalized as well.
+        if let Some(refs) = in_args.values_of("ref_dir") {
+            let ref_vec: Vec<String> = refs.iter().flat_map(|x| util::split_paths(x)).collect();
+            check_dirs(&ref_vec);
+
+            for x in ref_vec.iter() {
+                match fs::canonicalize(x) {
+                    Ok(t) => ref_path.push(t),
+                    Err(e) => {
+                        let err_str = format!("There was an error with the specified reference \
+                                              directory, {}: {}!", x, e);
+                        eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                        process::exit(1);
+                    }
+                }
+            }
 
-            archive_file = format!("{}/DuFF_{}.arch", out_dir, util::f_dt());
-        } else {
-            // This is  shitty solution but I need to be able to open a log file even if they don't
-            // want it.  If they don't want it I'll leave it hidden and will clean it up.
-            archive_file = format ! ("{}/.DuFF_{}.arch", out_dir, util::f_dt());
+            have_ref = true;
         }
 
-        if log {
 
-            log_file = format!("{}/DuFF_{}.log", out_dir, util::f_dt());
+        // If the user only wants unique files from under a certain directory, check it like the
+        // others and hang on to its canonicalized path for comparison against file paths later.
+        if let Some(uniq_d) = in_args.value_of_os("unique_root") {
+            check_dirs(&[&uniq_d]);
+
+            // check_dirs already made sure this exists so it should canonicalize just fine
+            unique_root = match fs::canonicalize(&uniq_d) {
+                Ok(t) => t,
+                Err(e) => {
+                    let err_str = format!("There was an error with the specified directory, {}: \
+                                          {}!", util::escape_path(&uniq_d), e);
+                    eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                    process::exit(1);
+                }
+            };
+
+            have_unique_root = true;
+        }
+
+
+        // If the user wants copies of particular files found, make sure each one is actually a
+        // file and store its canonicalized path.
+        if let Some(targets) = in_args.values_of_os("target") {
+            for x in targets.iter().flat_map(|x| util::split_os_paths(x)) {
+                let canon_target = match fs::canonicalize(&x) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        let err_str = format!("There was an error with the specified target file, \
+                                              {}: {}!", util::escape_path(x.as_os_str()), e);
+                        eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                        process::exit(1);
+                    }
+                };
+
+                if !canon_target.is_file() {
+                    let err_str = format!("Specified target file {} is not a file!",
+                                          util::escape_path(x.as_os_str()));
+                    eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                    process::exit(1);
+                }
+
+                target_ls.push(canon_target);
+            }
+
+            have_target = true;
+        }
+
+
+        // If the user already has a list of files for us we will read it in later, just take it as
+        // a string for now.
+        if let Some(files_f) = in_args.value_of("files_from") {
+            files_from = files_f.to_string();
+            have_files_from = true;
+        }
+
+
+        // Work out where the hash cache lives, if the user didn't tell us and we can't find a home
+        // directory to put it in then we just go without it.
+        if let Some(cache_d) = in_args.value_of("cache_dir") {
+            cache_dir = cache_d.to_string();
         } else {
-            // This is  shitty solution but I need to be able to open a log file even if they don't
-            // want it.  If they don't want it I'll leave it hidden and will clean it up.
-            log_file = format ! ("{}/.DuFF_{}.log", out_dir, util::f_dt());
+            match cache::default_cache_dir() {
+                Some(t) => cache_dir = t,
+                None => use_cache = false
+            }
+        }
+
+        // Same logic as for the number of jobs.
+        if let Some(c_size) = in_args.value_of("cache_size") {
+            match c_size.parse::<u64>() {
+                Ok(n) => cache_size = n,
+                Err(_e) => {
+                    let err_str = format!("Hash cache size specified, {}, is not a valid number!",
+                                          c_size);
+                    eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                    process::exit(1);
+                },
+            }
         }
 
+
+        // Other work
+
+        // Specify the paths for our working files, we'll create them later (and only if the user
+        // asked for them).
+        let archive_file = format!("{}/DuFF_{}.arch", out_dir, util::f_dt());
+        let log_file = format!("{}/DuFF_{}.log", out_dir, util::f_dt());
+
     let report_file = format!("{}/DuFF_{}.report", out_dir, util::f_dt());
 
         // I know a lot of these can be simplified due to the matching names, but I prefer
@@ -406,15 +843,45 @@ impl Config  {
             hide_prog: hide_prog,
             silent: silent,
             hide_err: hide_err,
+            src_dupes: src_dupes,
+            unique: unique,
+            empty: empty,
+            use_cache: use_cache,
+            relative_paths: relative_paths,
+            canonicalize: canonicalize,
+            sort_output: sort_output,
+            detect_types: detect_types,
 
             // Optional Arguments:
             ll_size: ll_size,
             ul_size: ul_size,
             jobs: jobs,
             exts: exts,
+            exclude_exts: exclude_exts,
+            newer_than: newer_than,
+            older_than: older_than,
+            owner_uids: owner_uids,
+            owner_gids: owner_gids,
+            perm: perm,
+            file_types: file_types,
+            min_depth: min_depth,
+            max_depth: max_depth,
+            skip_hidden: skip_hidden,
+            ignore_files: ignore_files,
             out_dir: out_dir,
             res_file: res_file,
             prev_hash_file: prev_hash_file,
+            hash_match: hash_match,
+            path_map: path_map,
+            ref_path: ref_path,
+            unique_root: unique_root,
+            target_ls: target_ls,
+            files_from: files_from,
+            dirs_from: dirs_from,
+            cache_dir: cache_dir,
+            cache_size: cache_size,
+            config_files: in_args.files.clone(),
+            profile: in_args.profile.clone(),
             
             // INTERNAL ARGUMENTS:
             archive_file: archive_file,
@@ -423,6 +890,10 @@ impl Config  {
 
             // INTERNAL FLAGS:
             resume: resume,
+            have_ref: have_ref,
+            have_unique_root: have_unique_root,
+            have_target: have_target,
+            have_files_from: have_files_from,
             have_hash: have_hash,
             user_set_dir: user_set_dir,
 
@@ -430,6 +901,120 @@ impl Config  {
     }
 }
 
+// The check_dirs function makes sure each of the user supplied directories is accessible and is
+// actually a directory, sending an error to stderr and exiting if not.
+// Arguments are as follows:
+// path_vec: The directories to check, as parsed from the comma separated user input.
+fn check_dirs<T: AsRef<Path>>(path_vec: &[T]) {
+    for x in path_vec.iter() {
+
+        // Getting a metadata object for the path string the user provided
+        // TODO: Write check to see if a dir w/o read perms returns OK or ERR
+        // TODO: Should we exit if there is an issue with one of the dirs or just try to contin?
+        match fs::metadata(x) {
+            Ok(m) => {
+
+                // Check to see if this is actually a directory and if it is not then send an
+                // error to stderr and exit, if it is a directory we should be good to go.
+                if m.is_dir() == false {
+                    let err_str = format!("Specified directory {} is not a directory!",
+                                          util::escape_path(x.as_ref().as_os_str()));
+                    eprintln!("{}", textwrap::fill(err_str.as_str(),
+                                                   textwrap::termwidth()));
+                    process::exit(1);
+                }
+            },
+            // If there was some unknown (to me) error then capture it and send it to stderr and
+            // exit
+            Err(e) => {
+                let err_str = format!("There was an error with the specified directory, {}: {}!",
+                                      util::escape_path(x.as_ref().as_os_str()), e);
+                eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+                process::exit(1);
+            }
+        }
+    }
+}
+
+// The expand_exts function splits up the extension lists the user gave us (see util::split_list),
+// swaps any @NAME group out for its extensions, and cleans each one up for matching (see
+// util::clean_ext). Groups from the config files win over the built in ones of the same name, and
+// an unknown group is an error.
+// Arguments are as follows:
+// in_exts: The -e (or --exclude-ext) values, each one an extension, group or list of them.
+// in_args: The merged command line and config file settings, holding the config file groups.
+fn expand_exts(in_exts: &[&str], in_args: &ArgSource) -> Vec<String> {
+    let mut exts = Vec::new();
+
+    for x in in_exts.iter().flat_map(|x| util::split_list(x)) {
+        match x.strip_prefix('@') {
+            Some(group) => {
+                let group_exts: Vec<String> = match in_args.ext_groups.get(group) {
+                    Some(t) => t.clone(),
+                    None => match EXT_GROUPS.iter().find(|y| y.0 == group) {
+                        Some(t) => t.1.iter().map(|y| y.to_string()).collect(),
+                        None => {
+                            let mut known: Vec<&str> = EXT_GROUPS.iter().map(|y| y.0).collect();
+                            known.extend(in_args.ext_groups.keys().map(|y| y.as_str()));
+                            filter_error(&format!("Unknown extension group @{} (known groups: {})!",
+                                                  group, known.join(", ")))
+                        }
+                    }
+                };
+
+                exts.extend(group_exts.iter().map(|y| util::clean_ext(y)));
+            }
+            None => exts.push(util::clean_ext(&x))
+        }
+    }
+
+    exts.retain(|x| !x.is_empty());
+    exts
+}
+
+// The parse_time_arg function reads in the date or age given for opt_str (see util::parse_time),
+// sending an error to stderr and exiting if it doesn't make sense.
+// Arguments are as follows:
+// opt_str: The option the value was given for, only used in the error message.
+// time_str: The date or age the user gave us.
+// now: The time ages are counted back from.
+fn parse_time_arg(opt_str: &str, time_str: &str, now: DateTime<Utc>) -> DateTime<Utc> {
+    match util::parse_time(time_str, now) {
+        Some(t) => t,
+        None => filter_error(&format!("{} {} is not a date (2021-06-30, 2021-06-30 14:00:00) or an \
+                                       age (12h, 90d, 2w, 1y)!", opt_str, time_str))
+    }
+}
+
+// The lookup_user and lookup_group functions turn a user or group name into its id, going through
+// the system's user database (so network accounts work too), returning None for unknown names.
+#[cfg(unix)]
+fn lookup_user(user_str: &str) -> Option<u32> {
+    uzers::get_user_by_name(user_str).map(|x| x.uid())
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_user_str: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn lookup_group(group_str: &str) -> Option<u32> {
+    uzers::get_group_by_name(group_str).map(|x| x.gid())
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_group_str: &str) -> Option<u32> {
+    None
+}
+
+// The filter_error function sends err_str to stderr and exits, for filters we couldn't make
+// sense of.
+fn filter_error(err_str: &str) -> ! {
+    eprintln!("{}", textwrap::fill(err_str, textwrap::termwidth()));
+    process::exit(1)
+}
+
 // Implementing the Display trait so that we can easily print out the DuFF configuration both out
 // to stdout as well as to a log file if needed.
 impl fmt::Display for Config {
@@ -442,18 +1027,70 @@ impl fmt::Display for Config {
                                  util::PROG_NAME.to_owned() + " v" + util::PROG_VERS).as_str());
         out_str.push_str(format!("{}\n", border_str).as_str());
 
+        if !self.config_files.is_empty() {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Config Files:", self.config_files.join(","))
+                .as_str());
+        }
+
+        if !self.profile.is_empty() {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Profile:", self.profile).as_str());
+        }
+
         if self.resume {
             out_str.push_str(format!("{:<40} {:>1}\n", "Status:", "Resuming" ).as_str());
+            out_str.push_str(format!("{:<40} {:>1}\n", "Resume Log:", self.res_file).as_str());
         } else {
             out_str.push_str(format!("{:<40} {:>1}\n", "Status:", "New Run" ).as_str());
         }
 
         out_str.push_str(format!("{:<40} {:>1}\n", "Search Directories:",
-                                 self.search_path.join(",")).as_str());
+                                 self.search_path.iter().map(|x| util::escape_path(x.as_os_str()))
+                                     .collect::<Vec<String>>().join(",")).as_str());
+
+        if !self.dirs_from.is_empty() {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Directory List:", self.dirs_from).as_str());
+        }
+
+        if self.have_files_from {
+            out_str.push_str(format!("{:<40} {:>1}\n", "File List:", self.files_from).as_str());
+        }
+
+        if self.have_ref {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Reference Directories:",
+                                     self.ref_path.iter().map(|x| util::escape_path(x.as_os_str()))
+                                         .collect::<Vec<String>>().join(",")).as_str());
+            out_str.push_str(format!("{:<40} {:>1}\n", "Report Source Only Duplicates:",
+                                     self.src_dupes).as_str());
+        }
+
+        if self.unique {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Find Unique Files:", self.unique).as_str());
+
+            if self.have_unique_root {
+                out_str.push_str(format!("{:<40} {:>1}\n", "Unique Files Under:",
+                                         util::escape_path(self.unique_root.as_os_str())).as_str());
+            }
+        }
+
+        if self.empty {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Report Empty Files and Directories:",
+                                     self.empty).as_str());
+        }
+
+        if self.have_target {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Find Copies Of:",
+                                     self.target_ls.iter().map(|x| util::escape_path(x.as_os_str()))
+                                         .collect::<Vec<String>>().join(",")).as_str());
+        }
 
         out_str.push_str(format!("{:<40} {:>1}\n", "Extensions:", self.exts.join(", "))
                 .as_str());
 
+        if !self.exclude_exts.is_empty() {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Excluded Extensions:",
+                                     self.exclude_exts.join(", ")).as_str());
+        }
+
 
         // Use the default value of ll to determine if the user specified one and if they did we
         // print it out, if they didn't we don't print it out so as to not confuse the user.
@@ -470,13 +1107,101 @@ impl fmt::Display for Config {
 
         }
 
+        if let Some(t) = self.newer_than {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Modified After:",
+                                     t.format("%Y-%m-%d %H:%M:%S UTC")).as_str());
+        }
+
+        if let Some(t) = self.older_than {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Modified Before:",
+                                     t.format("%Y-%m-%d %H:%M:%S UTC")).as_str());
+        }
+
+        if !self.owner_uids.is_empty() {
+            let uid_ls: Vec<String> = self.owner_uids.iter().map(|x| x.to_string()).collect();
+            out_str.push_str(format!("{:<40} {:>1}\n", "Owner User IDs:", uid_ls.join(","))
+                .as_str());
+        }
+
+        if !self.owner_gids.is_empty() {
+            let gid_ls: Vec<String> = self.owner_gids.iter().map(|x| x.to_string()).collect();
+            out_str.push_str(format!("{:<40} {:>1}\n", "Owner Group IDs:", gid_ls.join(","))
+                .as_str());
+        }
+
+        if let Some((how, bits)) = self.perm {
+            let how_str = if how == '=' { String::new() } else { how.to_string() };
+            out_str.push_str(format!("{:<40} {}{:03o}\n", "Permissions:", how_str, bits).as_str());
+        }
+
+        if !self.file_types.is_empty() {
+            out_str.push_str(format!("{:<40} {:>1}\n", "File Types:", self.file_types.join(", "))
+                .as_str());
+        } else if self.detect_types {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Detect File Types:", self.detect_types)
+                .as_str());
+        }
+
+        if self.canonicalize {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Canonicalize Paths:", self.canonicalize)
+                .as_str());
+        }
+
+        if !self.sort_output {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Sorted Report:", self.sort_output).as_str());
+        }
+
+        if self.min_depth > 0 {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Minimum Depth:", self.min_depth).as_str());
+        }
+
+        if let Some(depth) = self.max_depth {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Maximum Depth:", depth).as_str());
+        }
+
+        if self.skip_hidden {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Skip Hidden Files:", self.skip_hidden)
+                .as_str());
+        }
+
+        if self.ignore_files {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Honour Ignore Files:", self.ignore_files)
+                .as_str());
+        }
+
         out_str.push_str(format!("{:<40} {:>1}\n", "Number of Threads:", self.jobs).as_str());
+
+        if self.have_hash {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Previous Hashes:", self.prev_hash_file)
+                .as_str());
+            out_str.push_str(format!("{:<40} {:>1}\n", "Match Previous Hashes By:", self.hash_match)
+                .as_str());
+        }
+
+        if !self.path_map.is_empty() {
+            let map_ls: Vec<String> = self.path_map.iter()
+                .map(|(old, new)| format!("{}={}", old, new))
+                .collect();
+            out_str.push_str(format!("{:<40} {:>1}\n", "Path Maps:", map_ls.join(",")).as_str());
+        }
+
+        if self.use_cache {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Hash Cache:", self.cache_dir).as_str());
+        } else {
+            out_str.push_str(format!("{:<40} {:>1}\n", "Hash Cache:", self.use_cache).as_str());
+        }
+
         out_str.push_str(format!("{:<40} {:>1}\n", "Output Directory:", self.out_dir).as_str());
         out_str.push_str(format!("{:<40} {:>1}\n", "Final Report:", self.report_file).as_str());
 
         if self.archive {
             out_str.push_str(format!("{:<40} {:>1}\n", "Save Hashes:", self.archive_file).as_str());
 
+            if self.relative_paths {
+                out_str.push_str(format!("{:<40} {:>1}\n", "Relative Archive Paths:",
+                                         self.relative_paths).as_str());
+            }
+
         } else {
             out_str.push_str(format!("{:<40} {:>1}\n", "Save Hashes:", self.archive).as_str());
         }
@@ -488,7 +1213,7 @@ impl fmt::Display for Config {
             out_str.push_str(format!("{:<40} {:>1}\n", "Save Log:", self.log).as_str());
         }
 
-        if self.hide_prog == true {
+        if self.hide_prog {
             out_str.push_str(format!("{:<40} {:>1}\n", "Hide Progress:", self.hide_prog)
                 .as_str());
         }
@@ -0,0 +1,424 @@
+// This file/module reads DuFF's TOML configuration files, which let the same long list of
+// directories, extensions and size limits be reused without typing them out every time. Settings
+// are read from the system config file, then the user's, then any file given with --config, each
+// one overriding the last, and anything given on the command line overrides them all.
+//
+// A config file holds option names (the long command line names, e.g. dir, ext, lowlim) at the
+// top level, which apply to every run, and named profiles under [profiles.NAME] tables, which only
+// apply when picked with --profile NAME:
+//
+//     dir = ["/data/lab", "/data/shared"]
+//     lowlim = "1KB"
+//
+//     [profiles.sequencing]
+//     ext = ["bam", "fastq.gz"]
+//     archive = true
+//
+// Extension groups for -e @NAME (on top of the built in ones) go in an [ext_groups] table:
+//
+//     [ext_groups]
+//     reads = ["fastq", "fq", "fastq.gz", "fq.gz"]
+
+// For the locations of the system and user config files
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::collections::HashMap;
+use std::{env, fs, process};
+
+// The command line arguments, which win over anything in a config file, and the rules clap checks
+// them against, which config file settings have to follow too
+use clap::{load_yaml, ArgMatches};
+
+// A parsed config file (or profile)
+use toml::value::Table;
+
+
+// Where the system wide config file lives.
+const SYSTEM_CONFIG: &str = "/etc/duff/config.toml";
+
+// The option names allowed in config files, the long command line name first and the name clap
+// knows the argument by second. The command line only options (see COMMAND_LINE_ONLY) and the
+// subcommands aren't here.
+const OPTIONS: &[(&str, &str)] = &[
+    ("dir", "dir"),
+    ("dirs_from", "dirs_from"),
+    ("archive", "archive"),
+    ("log", "log"),
+    ("prog", "prog"),
+    ("silent", "silent"),
+    ("hide_err", "hide_err"),
+    ("src_dupes", "src_dupes"),
+    ("unique", "unique"),
+    ("empty", "empty"),
+    ("no_cache", "no_cache"),
+    ("lowlim", "lower_lim"),
+    ("uplim", "upper_lim"),
+    ("jobs", "jobs"),
+    ("ext", "exts"),
+    ("exclude_ext", "exclude_exts"),
+    ("newer_than", "newer_than"),
+    ("older_than", "older_than"),
+    ("user", "user"),
+    ("uid", "uid"),
+    ("group", "group"),
+    ("perm", "perm"),
+    ("out", "out_dir"),
+    ("hash", "hash"),
+    ("ref", "ref_dir"),
+    ("unique_under", "unique_root"),
+    ("target", "target"),
+    ("files_from", "files_from"),
+    ("cache_dir", "cache_dir"),
+    ("cache_size", "cache_size"),
+    ("hash_match", "hash_match"),
+    ("path_map", "path_map"),
+    ("relative_paths", "relative_paths"),
+    ("canonicalize", "canonicalize"),
+    ("unsorted", "unsorted"),
+    ("detect_types", "detect_types"),
+    ("type", "file_types"),
+    ("min_depth", "min_depth"),
+    ("max_depth", "max_depth"),
+    ("skip_hidden", "skip_hidden"),
+    ("ignore_files", "ignore_files"),
+];
+
+// The options that only make sense for the one run they are given for. resume in particular would
+// have every later run pick up the same old log if it was left in a config file.
+const COMMAND_LINE_ONLY: &[&str] = &["config", "profile", "unset", "resume"];
+
+// The options that can be given more than once on the command line, a list in a config file is
+// the same as giving each entry separately.
+const MULTIPLE: &[&str] = &["dir", "exts", "exclude_exts", "ref_dir", "target", "path_map", "user",
+                            "uid", "group", "file_types"];
+
+// The ArgSource struct merges the command line arguments with whatever the config files had to
+// say, and is what Config::new reads its settings from.
+pub struct ArgSource<'a> {
+
+    // The command line arguments from clap.
+    cli : &'a ArgMatches<'a>,
+
+    // The values from the config files, keyed by the name clap knows the argument by. Flags are
+    // stored as "true", and lists are joined with commas, except for the options that can be
+    // given more than once (see MULTIPLE), which keep one value per entry.
+    file_vals : HashMap<String, Vec<String>>,
+
+    // Where each of file_vals came from, the config file and the name the option was given as
+    // there, so errors can point at it.
+    origins : HashMap<String, (String, String)>,
+
+    // The config files we read, in the order we read them.
+    pub files : Vec<String>,
+
+    // The profile the user picked, empty if none.
+    pub profile : String,
+
+    // The extension groups defined in the config files, keyed by name (without the @).
+    pub ext_groups : HashMap<String, Vec<String>>,
+}
+
+impl<'a> ArgSource<'a> {
+
+    // The new function reads in the system, user and --config config files (any that exist) and
+    // applies the profile picked with --profile, sending an error to stderr and exiting if a file
+    // can't be understood or the profile isn't in any of them.
+    // Arguments are as follows:
+    // cli: The command line arguments from clap.
+    pub fn new(cli: &'a ArgMatches<'a>) -> ArgSource<'a> {
+        let mut source = ArgSource {
+            cli : cli,
+            file_vals : HashMap::new(),
+            origins : HashMap::new(),
+            files : Vec::new(),
+            profile : cli.value_of("profile").unwrap_or("").to_string(),
+            ext_groups : HashMap::new(),
+        };
+
+        // The system and user files are optional, a file given with --config is not.
+        let mut conf_paths: Vec<PathBuf> = vec![PathBuf::from(SYSTEM_CONFIG)];
+
+        if let Some(t) = user_config() {
+            conf_paths.push(t);
+        }
+
+        if let Some(t) = cli.value_of("config") {
+            if !Path::new(t).is_file() {
+                config_error(t, "no such file");
+            }
+            conf_paths.push(PathBuf::from(t));
+        }
+
+        let mut found_profile = false;
+
+        for x in conf_paths.iter().filter(|x| x.is_file()) {
+            let conf_str = x.display().to_string();
+
+            let conf_table: Table = match fs::read_to_string(x) {
+                Ok(t) => match toml::from_str(&t) {
+                    Ok(u) => u,
+                    Err(e) => config_error(&conf_str, &e.to_string())
+                },
+                Err(e) => config_error(&conf_str, &e.to_string())
+            };
+
+            source.apply(&conf_str, &conf_table);
+
+            if !source.profile.is_empty() {
+                if let Some(prof) = conf_table.get("profiles").and_then(|x| x.get(&source.profile)) {
+                    match prof.as_table() {
+                        Some(t) => source.apply(&conf_str, t),
+                        None => config_error(&conf_str, &format!("profile {} is not a table",
+                                                                 source.profile))
+                    }
+                    found_profile = true;
+                }
+            }
+
+            source.files.push(conf_str);
+        }
+
+        if !source.profile.is_empty() && !found_profile {
+            let err_str = format!("Profile {} was not found in any config file ({}).",
+                                  source.profile, conf_paths.iter()
+                                      .map(|x| x.display().to_string())
+                                      .collect::<Vec<String>>().join(", "));
+            eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+            process::exit(1);
+        }
+
+        // Anything the user unset on the command line is dropped before it gets checked.
+        for x in cli.values_of("unset").into_iter().flatten() {
+            match OPTIONS.iter().find(|y| y.0 == x.replace('-', "_")) {
+                Some(t) => {
+                    source.file_vals.remove(t.1);
+                    source.origins.remove(t.1);
+                }
+                None => {
+                    eprintln!("Can't unset {}, there is no such option.", x);
+                    process::exit(1);
+                }
+            }
+        }
+
+        // Directories listed at the end of the command line replace the ones in the config files,
+        // the same as -d does.
+        if cli.is_present("roots") {
+            source.file_vals.remove("dir");
+            source.origins.remove("dir");
+        }
+
+        source.check();
+
+        source
+    }
+
+    // The check function holds the config file settings to the same rules clap holds the command
+    // line to (see duff_args.yml): flags can only be true or false, options with a fixed set of
+    // values have to use one of them, options that conflict can't both be set and options that need
+    // another can't be set without it. We send an error naming the file and option to stderr and
+    // exit if any aren't followed.
+    fn check(&self) {
+        let yams = load_yaml!("duff_args.yml");
+
+        for x in yams["args"].as_vec().into_iter().flatten().filter_map(|x| x.as_hash()) {
+            for (k, v) in x.iter() {
+                let id = k.as_str().unwrap_or("");
+
+                // Each rule is a single value or a list of them.
+                let rule = |name: &str| -> Vec<String> {
+                    match (v[name].as_vec(), v[name].as_str()) {
+                        (Some(t), _) => t.iter().filter_map(|y| y.as_str()).map(String::from)
+                            .collect(),
+                        (None, Some(t)) => vec![t.to_string()],
+                        _ => Vec::new()
+                    }
+                };
+
+                // How an option is known in error messages, by where it was set if it was set in a
+                // config file, otherwise by its command line name.
+                let opt_str = |name: &str| -> String {
+                    match self.origins.get(name) {
+                        Some((f, o)) => format!("{} (set in {})", o, f),
+                        None => format!("--{}", yams["args"].as_vec().into_iter().flatten()
+                            .find_map(|y| y[name]["long"].as_str())
+                            .unwrap_or(name))
+                    }
+                };
+
+                for y in rule("conflicts_with").iter().filter(|y| self.is_present(y)) {
+                    if self.is_present(id) &&
+                        (self.origins.contains_key(id) || self.origins.contains_key(y)) {
+                        check_error(&format!("{} can't be used with {}.", opt_str(id),
+                                             opt_str(y)));
+                    }
+                }
+
+                let (conf_str, key) = match self.origins.get(id) {
+                    Some(t) => t,
+                    None => continue
+                };
+
+                let vals = &self.file_vals[id];
+                let values = rule("possible_values");
+
+                if v["takes_value"].as_bool() != Some(true) && (vals != &["true"]) {
+                    config_error(conf_str, &format!("{} is a flag, it can only be true or false",
+                                                    key));
+                }
+
+                if let Some(t) = vals.iter().find(|y| !values.is_empty() && !values.contains(y)) {
+                    config_error(conf_str, &format!("{} can't be {}, it has to be one of {}", key,
+                                                    t, values.join(", ")));
+                }
+
+                if let Some(y) = rule("requires").iter().find(|y| !self.is_present(y)) {
+                    check_error(&format!("{} needs {} to be set as well.", opt_str(id),
+                                         opt_str(y)));
+                }
+            }
+        }
+    }
+
+    // The apply function stores the options in conf_table, overriding anything read before.
+    // Arguments are as follows:
+    // conf_str: The config file the table came from, only used in error messages.
+    // conf_table: The table of options to store.
+    fn apply(&mut self, conf_str: &str, conf_table: &Table) {
+        for (k, v) in conf_table.iter() {
+            if k == "profiles" {
+                continue
+            }
+
+            if k == "ext_groups" {
+                self.apply_groups(conf_str, v);
+                continue
+            }
+
+            // Let people write the names with dashes like on the command line too.
+            let id = match OPTIONS.iter().find(|x| x.0 == k.replace('-', "_")) {
+                Some(t) => t.1,
+                None if COMMAND_LINE_ONLY.contains(&k.replace('-', "_").as_str()) => {
+                    config_error(conf_str, &format!("{} can only be given on the command line", k))
+                }
+                None => config_error(conf_str, &format!("unknown option {}", k))
+            };
+
+            let vals: Vec<String> = match v {
+                toml::Value::Array(t) => t.iter().map(value_str).collect(),
+                _ => vec![value_str(v)]
+            };
+
+            // Flags set to false are the same as leaving them out.
+            if vals == ["false"] {
+                self.file_vals.remove(id);
+                self.origins.remove(id);
+                continue
+            } else if MULTIPLE.contains(&id) {
+                self.file_vals.insert(id.to_string(), vals);
+            } else {
+                self.file_vals.insert(id.to_string(), vec![vals.join(",")]);
+            }
+
+            self.origins.insert(id.to_string(), (conf_str.to_string(), k.to_string()));
+        }
+    }
+
+    // The apply_groups function stores the extension groups in groups_val, an [ext_groups] table of
+    // lists, overriding any group of the same name read before.
+    // Arguments are as follows:
+    // conf_str: The config file the table came from, only used in error messages.
+    // groups_val: The [ext_groups] table.
+    fn apply_groups(&mut self, conf_str: &str, groups_val: &toml::Value) {
+        let groups_table = match groups_val.as_table() {
+            Some(t) => t,
+            None => config_error(conf_str, "ext_groups is not a table")
+        };
+
+        for (k, v) in groups_table.iter() {
+            let exts: Vec<String> = match v.as_array() {
+                Some(t) => t.iter().map(value_str).collect(),
+                None => config_error(conf_str, &format!("extension group {} is not a list", k))
+            };
+
+            self.ext_groups.insert(k.to_string(), exts);
+        }
+    }
+
+    // The is_present function returns whether the flag (or option) id was given on the command line
+    // or switched on in a config file.
+    pub fn is_present(&self, id: &str) -> bool {
+        self.cli.is_present(id) || self.file_vals.contains_key(id)
+    }
+
+    // The value_of function returns the value of option id from the command line, otherwise from
+    // the config files, or None if it wasn't given in either.
+    pub fn value_of(&self, id: &str) -> Option<&str> {
+        match self.cli.value_of(id) {
+            Some(t) => Some(t),
+            None => self.file_vals.get(id).map(|x| x[0].as_str())
+        }
+    }
+
+    // The values_of function does the same as value_of for options that can be given more than
+    // once.
+    pub fn values_of(&self, id: &str) -> Option<Vec<&str>> {
+        match self.cli.values_of(id) {
+            Some(t) => Some(t.collect()),
+            None => self.file_vals.get(id).map(|x| x.iter().map(|y| y.as_str()).collect())
+        }
+    }
+
+    // The value_of_os and values_of_os functions do the same as value_of and values_of for options
+    // holding paths, which on the command line don't have to be valid UTF-8.
+    pub fn value_of_os(&self, id: &str) -> Option<OsString> {
+        match self.cli.value_of_os(id) {
+            Some(t) => Some(t.to_os_string()),
+            None => self.file_vals.get(id).map(|x| OsString::from(&x[0]))
+        }
+    }
+
+    pub fn values_of_os(&self, id: &str) -> Option<Vec<OsString>> {
+        match self.cli.values_of_os(id) {
+            Some(t) => Some(t.map(OsString::from).collect()),
+            None => self.file_vals.get(id).map(|x| x.iter().map(OsString::from).collect())
+        }
+    }
+}
+
+// The value_str function turns a single TOML value into the string we would have gotten from the
+// command line.
+fn value_str(curr_val: &toml::Value) -> String {
+    match curr_val {
+        toml::Value::String(t) => t.to_string(),
+        _ => curr_val.to_string()
+    }
+}
+
+// The user_config function works out where the user's config file should be, following the XDG
+// convention of $XDG_CONFIG_HOME/duff/config.toml, falling back on ~/.config/duff/config.toml.
+fn user_config() -> Option<PathBuf> {
+    if let Ok(t) = env::var("XDG_CONFIG_HOME") {
+        if !t.is_empty() {
+            return Some(Path::new(&t).join("duff").join("config.toml"));
+        }
+    }
+
+    match env::var("HOME") {
+        Ok(t) if !t.is_empty() => Some(Path::new(&t).join(".config").join("duff").join("config.toml")),
+        _ => None
+    }
+}
+
+// The check_error function lets the user know which of their settings don't go together and exits.
+fn check_error(err_str: &str) -> ! {
+    eprintln!("{}", textwrap::fill(err_str, textwrap::termwidth()));
+    process::exit(1)
+}
+
+// The config_error function lets the user know what was wrong with which config file and exits.
+fn config_error(conf_str: &str, err_str: &str) -> ! {
+    let err_str = format!("Error reading config file {}: {}", conf_str, err_str);
+    eprintln!("{}", textwrap::fill(err_str.as_str(), textwrap::termwidth()));
+    process::exit(1)
+}
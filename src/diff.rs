@@ -0,0 +1,273 @@
+// This file/module holds the diff subcommand (duff diff), which compares the duplicates found by
+// two runs, e.g. last week's and this week's, listing the groups of duplicates that are new, the
+// ones that have been resolved, the ones that gained or lost copies, and how the space that could
+// be reclaimed has changed. Either run can be given as a report or as an archive, groups are
+// matched up by their ids (see util::group_id), which don't change from run to run.
+
+// For reading the inputs and writing out the differences
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::exit;
+
+// For writing out JSON
+use serde::Serialize;
+
+// For sizes the user can read
+use pretty_bytes::converter;
+
+// The diff subcommand gets its arguments from clap
+use clap::ArgMatches;
+
+use crate::archive;
+use crate::report;
+use crate::util;
+
+
+// The Group struct holds a single group of duplicates from one of the runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+
+    // The group's id, see util::group_id.
+    pub id : String,
+
+    // The size of each file in the group, in bytes.
+    pub size : u128,
+
+    // The full path of every copy in the group.
+    pub members : BTreeSet<String>,
+}
+
+impl Group {
+
+    // The reclaimable function returns how many bytes deleting all but one copy would free up.
+    pub fn reclaimable(&self) -> u128 {
+        self.size * (self.members.len().saturating_sub(1) as u128)
+    }
+}
+
+// The ChangedGroup struct holds a group found by both runs whose copies changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedGroup {
+
+    // The group's id and the size of each file in it.
+    pub id : String,
+    pub size : u128,
+
+    // The copies only the new run found, and the ones only the old run found.
+    pub gained : Vec<String>,
+    pub lost : Vec<String>,
+}
+
+// The RunDiff struct holds everything that changed between the two runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunDiff {
+
+    // The report or archive each run was read from.
+    pub old : String,
+    pub new : String,
+
+    // Groups only in the new run, and groups only in the old run.
+    pub new_groups : Vec<Group>,
+    pub resolved_groups : Vec<Group>,
+
+    // Groups in both runs that gained or lost copies.
+    pub changed_groups : Vec<ChangedGroup>,
+
+    // The bytes that could be reclaimed in each run, and the change from the old to the new.
+    pub reclaimable_old : u128,
+    pub reclaimable_new : u128,
+    pub reclaimable_change : i128,
+}
+
+// The run function is the entry point for the diff subcommand, reading in both runs and writing
+// out their differences in the format the user asked for.
+// Arguments are as follows:
+// in_args: The ArgMatches for the diff subcommand.
+pub fn run(in_args: &ArgMatches) {
+
+    // old and new are required and format has a default, so clap will have given us something.
+    let old_str = in_args.value_of("old").unwrap();
+    let new_str = in_args.value_of("new").unwrap();
+    let format = in_args.value_of("format").unwrap();
+
+    let run_diff = diff_groups(old_str, &read_groups(old_str), new_str, &read_groups(new_str));
+
+    let out_str = match format {
+        "json" => format!("{}\n", serde_json::to_string_pretty(&run_diff).unwrap()),
+        _ => to_text(&run_diff)
+    };
+
+    let write_res = match in_args.value_of("out") {
+        Some(t) => fs::write(t, out_str),
+        None => io::stdout().write_all(out_str.as_bytes())
+    };
+
+    if let Err(e) = write_res {
+        eprintln!("Error writing differences.\nError text: {}", e);
+        exit(1)
+    }
+}
+
+// The read_groups function reads the groups of duplicates out of the report or archive at in_str,
+// keyed by their ids. Archives (and logs) are JSON lines, so anything starting with a { is read as
+// one, grouping the hashed files by size and hash the same way a run does, otherwise it is read as
+// a report. Reports written before groups had ids can't be matched up, so we exit on those.
+// Arguments are as follows:
+// in_str: Path of the report or archive to read.
+fn read_groups(in_str: &str) -> BTreeMap<String, Group> {
+    let first_line = match File::open(in_str) {
+        Ok(t) => BufReader::new(t).lines()
+            .map_while(Result::ok)
+            .find(|x| !x.starts_with('#') && !x.trim().is_empty()),
+        Err(e) => {
+            eprintln!("Error reading {}.\nError text: {}", in_str, e);
+            exit(1)
+        }
+    };
+
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+
+    if first_line.is_some_and(|x| x.starts_with('{')) {
+        let (header, entries) = archive::read_records(in_str);
+        archive::check_algorithm(in_str, &header);
+
+        let mut by_hash: BTreeMap<(u128, String), Vec<String>> = BTreeMap::new();
+
+        for x in archive::resolve_relative(&header.roots, entries).into_iter()
+            .filter(|x| !x.hash.is_empty()) {
+            by_hash.entry((x.size, x.hash.clone())).or_default()
+                .push(util::escape_path(x.file_path.as_os_str()));
+        }
+
+        for ((size, hash), members) in by_hash.into_iter().filter(|x| x.1.len() > 1) {
+            let id = util::hash_id(&hash).unwrap_or(hash);
+            groups.insert(id.clone(), Group {id, size, members : members.into_iter().collect()});
+        }
+
+        return groups;
+    }
+
+    let (rows, incomplete) = report::read_report(in_str);
+
+    if incomplete {
+        eprintln!("Warning: {} is from a run that was interrupted, so it is incomplete.", in_str);
+    }
+
+    for x in rows {
+        let size = x.size;
+        let id = match x.group_id {
+            Some(t) => t,
+            None => {
+                eprintln!("Report {} has no group ids, it was written by an older DuFF. Re-run \
+                          DuFF (or diff its archive) to compare it.", in_str);
+                exit(1)
+            }
+        };
+
+        groups.entry(id.clone())
+            .or_insert_with(|| Group {id, size, members : BTreeSet::new()})
+            .members.insert(util::escape_path(Path::new(&util::unescape_path(&x.path))
+                                                .join(util::unescape_path(&x.name)).as_os_str()));
+    }
+
+    groups
+}
+
+// The diff_groups function works out what changed between the old and new runs' groups.
+// Arguments are as follows:
+// old_str, new_str: Where each run was read from.
+// old_groups, new_groups: Each run's groups, keyed by their ids.
+pub fn diff_groups(old_str: &str, old_groups: &BTreeMap<String, Group>, new_str: &str,
+                   new_groups: &BTreeMap<String, Group>) -> RunDiff {
+    let mut changed_groups = Vec::new();
+
+    for (id, new_g) in new_groups.iter() {
+        if let Some(old_g) = old_groups.get(id) {
+            let gained: Vec<String> = new_g.members.difference(&old_g.members).cloned().collect();
+            let lost: Vec<String> = old_g.members.difference(&new_g.members).cloned().collect();
+
+            if !gained.is_empty() || !lost.is_empty() {
+                changed_groups.push(ChangedGroup {id : id.clone(), size : new_g.size, gained,
+                                                  lost});
+            }
+        }
+    }
+
+    let reclaimable_old: u128 = old_groups.values().map(|x| x.reclaimable()).sum();
+    let reclaimable_new: u128 = new_groups.values().map(|x| x.reclaimable()).sum();
+
+    RunDiff {
+        old : old_str.to_string(),
+        new : new_str.to_string(),
+        new_groups : new_groups.values().filter(|x| !old_groups.contains_key(&x.id)).cloned()
+            .collect(),
+        resolved_groups : old_groups.values().filter(|x| !new_groups.contains_key(&x.id)).cloned()
+            .collect(),
+        changed_groups : changed_groups,
+        reclaimable_old : reclaimable_old,
+        reclaimable_new : reclaimable_new,
+        reclaimable_change : (reclaimable_new as i128) - (reclaimable_old as i128),
+    }
+}
+
+// The to_text function lays out the differences for people to read, a summary in the same layout
+// as the overview followed by the groups themselves.
+fn to_text(run_diff: &RunDiff) -> String {
+    let n_gained = run_diff.changed_groups.iter().filter(|x| !x.gained.is_empty()).count();
+    let n_lost = run_diff.changed_groups.iter().filter(|x| !x.lost.is_empty()).count();
+    let change_sign = if run_diff.reclaimable_change < 0 { "-" } else { "+" };
+
+    let mut out_str = String::new();
+
+    out_str.push_str(format!("{:<40} {:>1}\n", "Old Run:", run_diff.old).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "New Run:", run_diff.new).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "New Groups:", run_diff.new_groups.len()).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Resolved Groups:", run_diff.resolved_groups.len())
+        .as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Groups That Gained Copies:", n_gained).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Groups That Lost Copies:", n_lost).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Reclaimable (Old):",
+                             converter::convert(run_diff.reclaimable_old as f64)).as_str());
+    out_str.push_str(format!("{:<40} {:>1}\n", "Reclaimable (New):",
+                             converter::convert(run_diff.reclaimable_new as f64)).as_str());
+    out_str.push_str(format!("{:<40} {}{} ({} B)\n", "Reclaimable Change:", change_sign,
+                             converter::convert(run_diff.reclaimable_change.unsigned_abs() as f64),
+                             run_diff.reclaimable_change).as_str());
+
+    for (title, groups) in [("New groups", &run_diff.new_groups),
+                            ("Resolved groups", &run_diff.resolved_groups)].iter() {
+        if groups.is_empty() {
+            continue
+        }
+
+        out_str.push_str(format!("\n{}:\n", title).as_str());
+
+        for x in groups.iter() {
+            out_str.push_str(format!("  {} ({} B, {} copies)\n", x.id, x.size, x.members.len())
+                .as_str());
+
+            for y in x.members.iter() {
+                out_str.push_str(format!("    {}\n", y).as_str());
+            }
+        }
+    }
+
+    if !run_diff.changed_groups.is_empty() {
+        out_str.push_str("\nChanged groups:\n");
+
+        for x in run_diff.changed_groups.iter() {
+            out_str.push_str(format!("  {} ({} B)\n", x.id, x.size).as_str());
+
+            for y in x.gained.iter() {
+                out_str.push_str(format!("    + {}\n", y).as_str());
+            }
+            for y in x.lost.iter() {
+                out_str.push_str(format!("    - {}\n", y).as_str());
+            }
+        }
+    }
+
+    out_str
+}
@@ -0,0 +1,100 @@
+// This file/module holds the DirJob struct, the unit of work passed around the directory traversal
+// queues. Alongside the directory itself it carries how deep into its search directory it is (for
+// --min-depth and --max-depth) and the ignore files (.gitignore, .ignore and .duffignore) found in
+// it and the directories above it, so each thread can tell what to skip without looking back up
+// the tree.
+
+// For paths and sharing ignore rules between jobs
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// For parsing and matching ignore files
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+
+// The ignore files we honour, in order of increasing precedence.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", ".duffignore"];
+
+// The DirJob struct is a directory waiting to be searched.
+pub struct DirJob {
+
+    // The directory itself.
+    pub path : PathBuf,
+
+    // How deep this directory is in its search directory, the search directory itself is depth 0.
+    pub depth : usize,
+
+    // The ignore rules in effect for this directory, outermost first, shared with its siblings.
+    ignores : Arc<Vec<Gitignore>>
+}
+
+impl DirJob {
+
+    // The root function creates the job for a search directory.
+    // Arguments are as follows:
+    // path: The search directory.
+    pub fn root(path: PathBuf) -> DirJob {
+        DirJob {path, depth : 0, ignores : Arc::new(Vec::new())}
+    }
+
+    // The child function creates the job for a directory found inside this one, inheriting its
+    // ignore rules.
+    // Arguments are as follows:
+    // path: The directory found.
+    pub fn child(&self, path: PathBuf) -> DirJob {
+        DirJob {path, depth : self.depth + 1, ignores : Arc::clone(&self.ignores)}
+    }
+
+    // The load_ignores function reads any ignore files in this directory, adding their rules on
+    // top of the ones inherited. We're handed the names of the directory's entries, which we
+    // already have from reading it, so looking for ignore files doesn't cost a stat. Ignore files
+    // that can't be parsed are reported and whatever could be made sense of is still used.
+    // Arguments are as follows:
+    // entry_names: The names of everything in this directory.
+    pub fn load_ignores(&mut self, entry_names: &[OsString]) {
+        let mut builder = GitignoreBuilder::new(&self.path);
+        let mut found = false;
+
+        for x in IGNORE_FILES.iter().filter(|x| entry_names.iter().any(|y| y == *x)) {
+            let ignore_path = self.path.join(x);
+            found = true;
+
+            if let Some(e) = builder.add(&ignore_path) {
+                eprintln!("[Ignore file error] {}: {}", ignore_path.display(), e);
+            }
+        }
+
+        if !found {
+            return;
+        }
+
+        match builder.build() {
+            Ok(t) => {
+                let mut ignores = self.ignores.as_ref().clone();
+                ignores.push(t);
+                self.ignores = Arc::new(ignores);
+            }
+            Err(e) => eprintln!("[Ignore file error] {}: {}", self.path.display(), e)
+        }
+    }
+
+    // The is_ignored function checks the ignore rules in effect for an entry of this directory,
+    // the innermost ignore file with something to say about it winning (so a deeper ignore file
+    // can whitelist what an outer one ignores).
+    // Arguments are as follows:
+    // curr_path: The entry's path.
+    // is_dir: Whether the entry is a directory, as some rules only match directories.
+    pub fn is_ignored(&self, curr_path: &Path, is_dir: bool) -> bool {
+        for x in self.ignores.iter().rev() {
+            match x.matched(curr_path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => ()
+            }
+        }
+
+        false
+    }
+}
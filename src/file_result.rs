@@ -12,7 +12,7 @@ use std::fs::File;
 use std::fmt;
 
 // For reading a file in and hashing it
-use std::io::{BufReader, BufRead};
+use std::io::{self, BufReader, BufRead};
 
 // For storage of mtime
 use chrono::{DateTime, Utc};
@@ -26,20 +26,34 @@ use twox_hash::xxh3::{Hash128, HasherExt};
 // For serializing and deserializing FileResult objects
 use serde::{Serialize, Deserialize};
 
+// Paths are held as OsStrings/PathBufs so file names that aren't valid UTF-8 survive the trip
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// For displaying paths that aren't valid UTF-8
+use crate::util;
+
+// The hash algorithm we use, recorded in archive and log headers so hashes from different
+// algorithms never get compared.
+pub const HASH_ALGORITHM: &str = "xxh3_128";
+
 // The FileResult struct will hold information for each file of potential interest to us.  A struct
 // will only be created if the file represented by the file_path meets both any user specified lower
 // or upper file size limit and any user specified extension requirements.
 #[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub struct FileResult {
 
-    // The actual file name, as a String
-    pub file_name : String,
+    // The actual file name, as an OsString so names that aren't valid UTF-8 are kept as they are.
+    #[serde(with = "os_str_serde")]
+    pub file_name : OsString,
 
-    // The directory path within which file_name resides, as a String
-    pub dir_path : String,
+    // The directory path within which file_name resides.
+    #[serde(with = "os_str_serde")]
+    pub dir_path : PathBuf,
 
-    // The full path to the file, held as a String for convenience. [dir_path/file_name]
-    pub file_path : String,
+    // The full path to the file. [dir_path/file_name]
+    #[serde(with = "os_str_serde")]
+    pub file_path : PathBuf,
 
     // The size of this file as a u128 to future proof us (hopefully).
     pub size : u128,
@@ -49,7 +63,30 @@ pub struct FileResult {
     pub mtime: DateTime<Utc>,
 
     // The XXX3 128-bit hash stored as a string for convenience.
-    pub hash : String
+    pub hash : String,
+
+    // The device and inode numbers identifying this file on disk, along with its ctime (in
+    // nanoseconds since the epoch). These are what the hash cache is keyed on, along with size and
+    // mtime. They are left at 0 on platforms that don't have them, and default to 0 when reading in
+    // logs and archives written before we started saving them.
+    #[serde(default)]
+    pub dev : u64,
+
+    #[serde(default)]
+    pub inode : u64,
+
+    #[serde(default)]
+    pub ctime : i64,
+
+    // The type of file this is going by its contents (see file_type.rs), only filled in when the
+    // user asks for types to be detected, otherwise left empty.
+    #[serde(default)]
+    pub file_type : String,
+
+    // For archives written with relative paths, which of the roots in the archive's header the
+    // paths are relative to (as an index into them). Left out of every other record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root : Option<usize>
 }
 
 impl FileResult {
@@ -57,23 +94,25 @@ impl FileResult {
     // Simple new function, note that with how DuFF currently functions we do not have a hash when
     // the FileResult object is first created, so we set it to an empty string here. There is an
     // update_hash function below that allows us to update the hash later after we calculate it.
-    pub fn new(file_name: String, dir_path: String, file_path: String, size: u128,
-               mtime: DateTime<Utc>) -> FileResult {
-        FileResult {file_name, dir_path, file_path, size, mtime, hash : String::new()}
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(file_name: OsString, dir_path: PathBuf, file_path: PathBuf, size: u128,
+               mtime: DateTime<Utc>, dev: u64, inode: u64, ctime: i64) -> FileResult {
+        FileResult {file_name, dir_path, file_path, size, mtime, hash : String::new(), dev, inode,
+                    ctime, file_type : String::new(), root : None}
     }
 
     // The calc_hash function does what it says its going to do, calculate a hash, specifically as
-    // currently implemented a(n) XXH3 128-bit hash. It does not return a value, instead directly
-    // updating the hash associated with self.
+    // currently implemented a(n) XXH3 128-bit hash. Rather than returning the hash it directly
+    // updates the hash associated with self, only returning an error if the file couldn't be read,
+    // in which case the hash is left alone.
     // Arguments are as follows:
     // buff_size: The size of the BuffReader buffer capacity, as a usize.  This was used when doing
     //            testing, but for now at least there is a hard-coded value in the main code, so
     //            currently this argument is of little value.
-    pub fn calc_hash(&mut self, buff_size: usize) {
+    pub fn calc_hash(&mut self, buff_size: usize) -> io::Result<()> {
 
         // Open the file for reading to hash it
-        // TODO: Handle this unwrap!
-        let f = File::open(&self.file_path).unwrap();
+        let f = File::open(&self.file_path)?;
 
         // Create the BufReader for the file f with supplied buff_size.
         let mut f = BufReader::with_capacity(buff_size, f);
@@ -87,7 +126,7 @@ impl FileResult {
         // Loop until we run out of file, hashing as we go!
         loop {
             let consumed = {
-                let bytes = f.fill_buf().unwrap();
+                let bytes = f.fill_buf()?;
                 if bytes.is_empty() {
                     break;
                 }
@@ -103,6 +142,7 @@ impl FileResult {
         // Update self's hash variable with this newly calculated hash function.
         self.update_hash(hash);
 
+        Ok(())
     }
 
     // The update_hash function simply takes a string and sets that string as the hash for the file
@@ -122,7 +162,8 @@ impl fmt::Display for FileResult {
 
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 
-        write!(f, "{} {} {} {}", self.size, self.hash, self.mtime, self.file_path)
+        write!(f, "{} {} {} {}", self.size, self.hash, self.mtime,
+               util::escape_path(self.file_path.as_os_str()))
     }
 }
 
@@ -146,4 +187,79 @@ impl PartialEq for FileResult {
     fn eq(&self, other: &Self) -> bool {
         (self.size, &self.hash) == (other.size, &other.hash)
     }
-}
\ No newline at end of file
+}
+
+// The os_str_serde module (de)serializes the path fields of FileResult. Paths that are valid UTF-8
+// are written as plain strings, exactly as they always have been, and anything else is written as
+// {"hex": "..."} holding the raw bytes of the path, so logs and archives round trip every path
+// without loss.
+pub mod os_str_serde {
+    use std::ffi::{OsStr, OsString};
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+    use crate::util;
+
+    // The two shapes a path can take in a log or archive.
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum EncodedPath {
+        Text(String),
+        Bytes { hex: String },
+    }
+
+    fn encode(curr_path: &OsStr) -> EncodedPath {
+        match curr_path.to_str() {
+            Some(t) => EncodedPath::Text(t.to_string()),
+            None => EncodedPath::Bytes {
+                hex: util::os_bytes(curr_path).iter().map(|x| format!("{:02x}", x)).collect()
+            }
+        }
+    }
+
+    fn decode(encoded: EncodedPath) -> Result<OsString, String> {
+        match encoded {
+            EncodedPath::Text(t) => Ok(OsString::from(t)),
+            EncodedPath::Bytes { hex } => {
+                if (hex.len() % 2 != 0) || !hex.is_ascii() {
+                    return Err(format!("invalid hex path {}", hex));
+                }
+
+                let path_bytes = (0..hex.len()).step_by(2)
+                    .map(|x| u8::from_str_radix(&hex[x..x + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| format!("invalid hex path {}", hex))?;
+
+                Ok(util::os_from_bytes(path_bytes))
+            }
+        }
+    }
+
+    pub fn serialize<T: AsRef<OsStr>, S: Serializer>(curr_path: &T, ser: S)
+                                                    -> Result<S::Ok, S::Error> {
+        encode(curr_path.as_ref()).serialize(ser)
+    }
+
+    pub fn deserialize<'de, T: From<OsString>, D: Deserializer<'de>>(de: D) -> Result<T, D::Error> {
+        decode(EncodedPath::deserialize(de)?).map(T::from).map_err(D::Error::custom)
+    }
+
+    // The same again for lists of paths, like the roots in an archive's header.
+    pub mod vec {
+        use std::ffi::{OsStr, OsString};
+        use serde::{Serialize, Serializer, Deserialize, Deserializer};
+        use serde::de::Error;
+        use super::{encode, decode, EncodedPath};
+
+        pub fn serialize<T: AsRef<OsStr>, S: Serializer>(path_ls: &[T], ser: S)
+                                                        -> Result<S::Ok, S::Error> {
+            path_ls.iter().map(|x| encode(x.as_ref())).collect::<Vec<EncodedPath>>().serialize(ser)
+        }
+
+        pub fn deserialize<'de, T, D>(de: D) -> Result<Vec<T>, D::Error>
+            where T: From<OsString>, D: Deserializer<'de> {
+            Vec::<EncodedPath>::deserialize(de)?.into_iter()
+                .map(|x| decode(x).map(T::from).map_err(D::Error::custom))
+                .collect()
+        }
+    }
+}
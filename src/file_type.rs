@@ -0,0 +1,209 @@
+// This file/module works out what kind of file something really is from its first few bytes (its
+// "magic" bytes), rather than trusting its extension, since gzipped FASTQs without a .gz and BAMs
+// renamed to .tmp are all too common. Gzip (and BGZF) files are peeked inside of as well, so a BAM
+// shows up as a bam and a gzipped FASTQ as fastq.gz instead of both just being gzip.
+
+// For reading the start of the file
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// For peeking inside gzip files
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide, inflate_flags};
+
+
+// How much of a file we normally look at.
+const SNIFF_BYTES: u64 = 4096;
+
+// How much of a gzip file we look at, enough to hold the whole first block of a BGZF file (which
+// are at most 64KB), and with it the start of a BAM.
+const GZIP_SNIFF_BYTES: u64 = 65536;
+
+// How much decompressed data we look at inside a gzip file.
+const INNER_BYTES: usize = 4096;
+
+// Every type detect can hand back, used to check the user's --type filters.
+pub const TYPES: &[&str] = &["gzip", "bgzf", "bam", "fastq.gz", "fasta.gz", "vcf.gz", "sam.gz",
+                             "cram", "zip", "pdf", "png", "jpeg", "gif", "tiff", "hdf5", "bzip2",
+                             "xz", "zstd", "tar", "elf", "fastq", "fasta", "sam", "vcf", "text",
+                             "binary", "empty", "directory", "unknown"];
+
+// The detect function reads the start of the file at curr_path and returns its type (one of
+// TYPES), "unknown" if the file can't be read. Directories never get this far, main gives the
+// empty directories it reports the "directory" type itself.
+// Arguments are as follows:
+// curr_path: The file to look at.
+pub fn detect(curr_path: &Path) -> String {
+    let mut head = Vec::new();
+
+    let read_res = File::open(curr_path).and_then(|f| {
+        let mut f = f.take(SNIFF_BYTES);
+        f.read_to_end(&mut head)?;
+
+        // Gzip files get a bigger look, see GZIP_SNIFF_BYTES
+        if head.starts_with(&[0x1f, 0x8b]) {
+            f.set_limit(GZIP_SNIFF_BYTES - SNIFF_BYTES);
+            f.read_to_end(&mut head)?;
+        }
+
+        Ok(())
+    });
+
+    match read_res {
+        Ok(_) if head.is_empty() => String::from("empty"),
+        Ok(_) => classify(&head).to_string(),
+        Err(_) => String::from("unknown")
+    }
+}
+
+// The classify function works out the type from the first bytes of a file.
+// Arguments are as follows:
+// head: The start of the file.
+fn classify(head: &[u8]) -> &'static str {
+    if head.starts_with(&[0x1f, 0x8b, 0x08]) {
+        return classify_gzip(head);
+    }
+
+    let magic: &[(&[u8], &str)] = &[
+        (b"CRAM", "cram"),
+        (b"PK\x03\x04", "zip"),
+        (b"PK\x05\x06", "zip"),
+        (b"%PDF-", "pdf"),
+        (b"\x89PNG\r\n\x1a\n", "png"),
+        (b"\xff\xd8\xff", "jpeg"),
+        (b"GIF87a", "gif"),
+        (b"GIF89a", "gif"),
+        (b"II*\x00", "tiff"),
+        (b"MM\x00*", "tiff"),
+        (b"\x89HDF\r\n\x1a\n", "hdf5"),
+        (b"BZh", "bzip2"),
+        (b"\xfd7zXZ\x00", "xz"),
+        (b"\x28\xb5\x2f\xfd", "zstd"),
+        (b"\x7fELF", "elf"),
+    ];
+
+    if let Some(t) = magic.iter().find(|x| head.starts_with(x.0)) {
+        return t.1;
+    }
+
+    if (head.len() > 262) && (&head[257..262] == b"ustar") {
+        return "tar";
+    }
+
+    classify_text(head).unwrap_or("binary")
+}
+
+// The classify_gzip function works out what is inside a gzip file, returning bam, fastq.gz and so
+// on when we can tell, otherwise bgzf (block gzip, as written by bgzip and samtools) or gzip.
+// Arguments are as follows:
+// head: The start of the gzip file.
+fn classify_gzip(head: &[u8]) -> &'static str {
+    let (is_bgzf, data_start) = match gzip_header(head) {
+        Some(t) => t,
+        None => return "gzip"
+    };
+
+    let inner = inflate_start(&head[data_start..]);
+
+    if inner.starts_with(b"BAM\x01") {
+        return "bam";
+    }
+
+    match classify_text(&inner) {
+        Some("fastq") => "fastq.gz",
+        Some("fasta") => "fasta.gz",
+        Some("vcf") => "vcf.gz",
+        Some("sam") => "sam.gz",
+        _ if is_bgzf => "bgzf",
+        _ => "gzip"
+    }
+}
+
+// The gzip_header function reads the header of a gzip member, returning whether it is a BGZF
+// block (an extra field with the BC subfield) and where the compressed data starts, or None if the
+// header doesn't fit in head.
+// Arguments are as follows:
+// head: The start of the gzip file.
+fn gzip_header(head: &[u8]) -> Option<(bool, usize)> {
+    let flags = *head.get(3)?;
+    let mut pos = 10;
+    let mut is_bgzf = false;
+
+    // FEXTRA
+    if flags & 0x04 != 0 {
+        let xlen = u16::from_le_bytes([*head.get(pos)?, *head.get(pos + 1)?]) as usize;
+        let extra = head.get(pos + 2..pos + 2 + xlen)?;
+        let mut sub = 0;
+
+        while sub + 4 <= extra.len() {
+            let slen = u16::from_le_bytes([extra[sub + 2], extra[sub + 3]]) as usize;
+
+            if (extra[sub] == b'B') && (extra[sub + 1] == b'C') && (slen == 2) {
+                is_bgzf = true;
+            }
+            sub += 4 + slen;
+        }
+
+        pos += 2 + xlen;
+    }
+
+    // FNAME and FCOMMENT, both NUL terminated
+    for x in [0x08, 0x10].iter() {
+        if flags & x != 0 {
+            pos += head.get(pos..)?.iter().position(|y| *y == 0)? + 1;
+        }
+    }
+
+    // FHCRC
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+
+    if pos <= head.len() { Some((is_bgzf, pos)) } else { None }
+}
+
+// The inflate_start function decompresses as much of the start of a deflate stream as it can (up
+// to INNER_BYTES), stopping quietly wherever the data we have runs out or doesn't make sense.
+// Arguments are as follows:
+// data: The start of the deflate stream.
+fn inflate_start(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0; INNER_BYTES];
+    let flags = inflate_flags::TINFL_FLAG_HAS_MORE_INPUT |
+        inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+
+    let (_, _, n_out) = decompress(&mut DecompressorOxide::new(), data, &mut out, 0, flags);
+
+    out.truncate(n_out);
+    out
+}
+
+// The classify_text function recognizes the text formats we care about (FASTQ, FASTA, SAM, VCF),
+// calling anything else that looks like text just text, and returns None for binary data.
+// Arguments are as follows:
+// head: The start of the file (or of the decompressed data).
+fn classify_text(head: &[u8]) -> Option<&'static str> {
+    if head.is_empty() || head.contains(&0) {
+        return None;
+    }
+
+    // A multibyte character may have been cut off at the end, that's fine.
+    let text = match std::str::from_utf8(head) {
+        Ok(t) => t,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None
+    };
+
+    let lines: Vec<&str> = text.lines().take(4).collect();
+
+    if text.starts_with("##fileformat=VCF") {
+        Some("vcf")
+    } else if text.starts_with("@HD\t") || text.starts_with("@SQ\t") {
+        Some("sam")
+    } else if text.starts_with('@') && (lines.len() >= 3) && lines[2].starts_with('+') {
+        Some("fastq")
+    } else if text.starts_with('>') {
+        Some("fasta")
+    } else {
+        Some("text")
+    }
+}
@@ -0,0 +1,57 @@
+// This file/module handles Ctrl-C (SIGINT) and SIGTERM, which is what batch schedulers send when a
+// job hits its walltime. Rather than dying on the spot and losing everything, the first signal just
+// flips a flag. The rest of DuFF checks that flag before starting any new piece of work, lets the
+// work already in flight finish, saves what it has (log, archive and hash cache, so a re-run picks
+// up where this one stopped) and writes out a report marked as incomplete before exiting with
+// EXIT_CODE. A second signal means the user really wants out, so we exit immediately.
+
+// The flag is shared between the signal handler and every worker thread.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// To quit immediately on a second signal.
+use std::process::exit;
+
+
+// The exit code DuFF uses when it stopped early because of a signal, the usual 128 + SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+// Set once we've been asked to stop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// The watch function installs our handler for SIGINT and SIGTERM. Failing to install it isn't
+// worth stopping over, we just lose the graceful shutdown.
+pub fn watch() {
+    let handler_res = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\nInterrupted again, exiting immediately.");
+            exit(EXIT_CODE);
+        }
+
+        eprintln!("\nInterrupted, finishing the files in progress and saving what we have (interrupt \
+                  again to exit immediately)...");
+    });
+
+    if let Err(e) = handler_res {
+        eprintln!("Error setting up signal handling, DuFF will not be able to shut down gracefully.\
+                  \nError text: {}", e);
+    }
+}
+
+// The interrupted function returns true if we've been asked to stop, in which case no new work
+// should be started.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// The exit_code function works out what DuFF should exit with at the end of a run, EXIT_CODE if we
+// were interrupted, otherwise 1 if the log or archive couldn't be written (see clean_up) and 0 if
+// everything went to plan.
+pub fn exit_code(written_ok: bool) -> i32 {
+    if interrupted() {
+        EXIT_CODE
+    } else if !written_ok {
+        1
+    } else {
+        0
+    }
+}
@@ -1,22 +1,51 @@
 // I sometimes use extra parens to make thing more readable to me
 #![allow(unused_parens)]
 
+// Same goes for explicit returns, spelled out struct fields and matches with a single arm, clippy
+// would rather I didn't, but I find them easier to follow.
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::single_match,
+         clippy::while_let_loop)]
+
+// And for the way the rest of the original code was written, which is left as it is rather than
+// reworked just to quiet clippy.
+#![allow(clippy::bool_comparison, clippy::collapsible_match, clippy::needless_borrow,
+         clippy::ptr_arg, clippy::redundant_static_lifetimes,
+         clippy::single_component_path_imports, clippy::to_string_in_format_args,
+         clippy::unwrap_or_default, clippy::useless_conversion)]
+
 mod util;
 mod config;
+mod config_file;
 mod file_result;
+mod cache;
+mod archive;
+mod writer;
+mod report;
+mod interrupt;
+mod file_type;
+mod stats;
+mod dir_job;
+mod diff;
+mod act;
 
 // To use our wrapper function for creating files for writing to.
 use self::util::open_file;
 
+// The directories waiting to be searched.
+use self::dir_job::DirJob;
+
 // Standard library stuff:
 // For file paths and such
 use std::path::PathBuf;
 
-// For writing to our output files.
-use std::io::{Write, BufReader, BufRead};
+// For the names of directory entries, when looking for ignore files.
+use std::ffi::OsString;
+
+// For canonicalizing the search directories and carrying file metadata forward from traversal.
+use std::fs::{self, Metadata};
 
 // For deduplicating we use a hashmap struct to make it a bit easier.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 // To quit early if errors are detected that cannot be dealt with.
 use std::process::exit;
@@ -42,8 +71,7 @@ use console::{Emoji, style};
 
 // Miscellaneous crates
 // For dealing with command line arguments
-use clap::{load_yaml, App};
-use std::fs::File;
+use clap::{load_yaml, App, Arg, SubCommand};
 use shh;
 
 
@@ -65,10 +93,62 @@ fn main() {
 
     // Get user input
     let yams = load_yaml!("duff_args.yml");
-    let matches = App::from(yams).get_matches();
+
+    // The scan options are only listed once, at the top level of duff_args.yml (which is what makes
+    // duff -d ... work without a subcommand), so share them with the scan and resume subcommands
+    // here. resume takes the log as its first argument instead of through --resume, in place of
+    // the search directories.
+    let scan_yams = yams["args"].as_vec().unwrap();
+
+    let scan_opts: Vec<Arg> = scan_yams.iter()
+        .map(|x| Arg::from_yaml(x.as_hash().unwrap()))
+        .collect();
+
+    let resume_opts: Vec<Arg> = scan_yams.iter()
+        .filter(|x| x["resume"].is_badvalue() && x["roots"].is_badvalue())
+        .map(|x| Arg::from_yaml(x.as_hash().unwrap()))
+        .collect();
+
+    let matches = App::from(yams)
+        .subcommand(SubCommand::with_name("scan")
+            .about("Search for duplicate files (the default when no subcommand is given).")
+            .display_order(1)
+            .args(&scan_opts))
+        .subcommand(SubCommand::with_name("resume")
+            .about("Resume a previous DuFF run from its log (see 'log' option).")
+            .display_order(2)
+            .arg(Arg::with_name("resume")
+                .help("Path to the log file from the run to resume.")
+                .required(true)
+                .index(1))
+            .args(&resume_opts))
+        .get_matches();
+
+    // Archive maintenance, report conversion, diffing runs and acting on reports are their own
+    // thing, hand them off and we're done. Everything else is a scan, with or without the subcommand.
+    let scan_args = match matches.subcommand() {
+        ("archive", Some(sub_args)) => {
+            archive::run(sub_args);
+            exit(0)
+        }
+        ("report", Some(sub_args)) => {
+            report::run(sub_args);
+            exit(0)
+        }
+        ("diff", Some(sub_args)) => {
+            diff::run(sub_args);
+            exit(0)
+        }
+        ("act", Some(sub_args)) => {
+            act::run(sub_args);
+            exit(0)
+        }
+        ("scan", Some(sub_args)) | ("resume", Some(sub_args)) => sub_args.clone(),
+        _ => matches.clone()
+    };
 
     // Process user input
-    let conf = config::Config::new(matches);
+    let conf = config::Config::new(scan_args);
 
     // Give the user some immediate feedback that DuFF is running.
     if !conf.hide_prog {
@@ -93,16 +173,19 @@ fn main() {
     let report_file = open_file(&conf.report_file, &conf.out_dir,
                                     conf.user_set_dir);
 
-    // Open the log file for writing - this file is hidden if the user didn't want it and will be
-    // cleaned up.
-    let mut log_file = open_file(&conf.log_file, &conf.out_dir,
-                                     conf.user_set_dir);
+    // From here on a Ctrl-C or SIGTERM winds the run down gracefully instead of killing it.
+    interrupt::watch();
+
+    // Start up the writers for the log and archive files, these do nothing if the user didn't ask
+    // for the file.
+    let mut log_writer = writer::RecordWriter::start(&conf.log_file, conf.log, &conf.out_dir,
+                                                     conf.user_set_dir);
 
+    let mut arch_writer = writer::RecordWriter::start(&conf.archive_file, conf.archive,
+                                                      &conf.out_dir, conf.user_set_dir);
 
-    // Open the archive file for writing - this file is hidden if the user didn't want it and will
-    // be cleaned up.
-    let arch_file = open_file(&conf.archive_file, &conf.out_dir,
-                                 conf.user_set_dir);
+    // The canonicalized search and reference directories, for writing archive paths relative to.
+    let roots = util::canon_roots(&conf);
 
     // Create dictionary for hashes from previous run.
     let mut prev_dict: HashMap<u128, Vec<file_result::FileResult>> = HashMap::new();
@@ -112,63 +195,101 @@ fn main() {
         println!("{}", conf)
     }
 
-    // Write out the configuration to the log file
+    // Write the header out to the log file, followed by the configuration as comments for anyone
+    // reading it.
     if conf.log {
+        log_writer.write(archive::Header::from_conf("log", &conf).to_line());
 
-        // TODO: Replace unwrap
-        writeln!(log_file, "#Config\n{}\n#Starting file search\n", conf).unwrap();
+        for x in conf.to_string().lines() {
+            log_writer.write(format!("# {}", x));
+        }
+
+        log_writer.write(String::from("# Starting file search"));
     }
 
+    // Same goes for the archive, minus the configuration.
+    if conf.archive {
+        arch_writer.write(archive::Header::from_conf("archive", &conf).to_line());
+    }
+
+
+    // When resuming a previous run, its log tells us which files to examine (so we can skip the
+    // directory traversal) and gives us the hashes it had already calculated.
+    let mut resume_ls: Vec<PathBuf> = Vec::new();
 
-    // Logic to handle a resuming of previous DuFF run using log file from that run
     if conf.resume {
+        let (examined, hashed) = archive::read_log(&conf.res_file, &conf);
+        resume_ls = examined;
 
+        for curr_obj in hashed {
+            prev_dict.entry(curr_obj.size).or_insert(Vec::new()).push(curr_obj);
+        }
     }
 
     // Logic to handle hash file from previous DuFF run
     if conf.have_hash {
-        let prev_hash_file = match File::open(&conf.prev_hash_file) {
-            Ok(t) => t,
-            Err(e) => {
+        for curr_obj in archive::read_archive(&conf.prev_hash_file, &conf) {
+
+            // Thanks to this SO answer: https://stackoverflow.com/a/33243862
+            prev_dict.entry(curr_obj.size).or_insert(Vec::new()).push(curr_obj);
+        }
+    }
 
-                // If we can't read the file kill the program
-                eprintln!("[Error Reading previous hash file] {}", e);
-                println!("Error reading input previous hash file {}. \nPlease fix and try to re-run \
-                        or re-run without using this file.", &conf.prev_hash_file);
-                exit(1)
 
-            }
-        };
+    // 512 KiB BufReader size
+    let buff_size = 524288;
 
-        let hash_reader = BufReader::new(prev_hash_file);
+    // Load up the persistent hash cache, which is consulted before we calculate any hash.
+    let mut hash_cache = cache::HashCache::load(&conf);
 
-        for line in hash_reader.lines() {
-            let curr_line = match line {
-                Ok(t) => t,
-                Err(_) => continue
-            };
+    // If the user wants copies of specific files found, get their sizes and hashes up front so we
+    // know what we are looking for before we go looking.
+    let mut targets: Vec<file_result::FileResult> = Vec::new();
 
-            let curr_obj: file_result::FileResult = match serde_json::from_str(&curr_line) {
-                Ok(t) => t,
+    if conf.have_target {
+        if !conf.hide_prog {
+            println!("[{}, {}] {} Calculating target file hashes...",
+                     util::dt(),
+                     style("01/11").bold().dim(),
+                     ROBOT
+            );
+        }
 
-                Err(_) => {
-                    // Error reading line, just skip to next
-                    continue
+        for x in conf.target_ls.iter() {
+            match util::build_result(x, None, true) {
+                Some((mut t, _)) => {
+                    let hash_res = match hash_cache.get(&t) {
+                        Some(h) => {
+                            t.update_hash(h);
+                            Ok(())
+                        }
+                        None => t.calc_hash(buff_size)
+                    };
 
-                }
-            };
+                    if let Err(e) = hash_res {
+                        eprintln!("Error reading target file {}: {}",
+                                  util::escape_path(x.as_os_str()), e);
+                        exit(1)
+                    }
 
-            //let fs: u128 = t.size;
-            // Thanks to this SO answer: https://stackoverflow.com/a/33243862
-            prev_dict.entry(curr_obj.size).or_insert(Vec::new()).push(curr_obj);
+                    hash_cache.update(&t);
+                    targets.push(t);
+                }
+                None => {
+                    eprintln!("Error examining target file {}.", util::escape_path(x.as_os_str()));
+                    exit(1)
+                }
+            }
         }
-
     }
 
-
     // Create our channels that we will use to send the files we find during directory traversal
-    // down for further processing later.
+    // down for further processing later, along with their metadata when the DirEntry gave it to us.
     let (tx, rx) =
+        crossbeam_channel::unbounded::<(PathBuf, Option<Metadata>)>();
+
+    // And one for any empty directories we come across, if the user wants those reported.
+    let (dir_tx, dir_rx) =
         crossbeam_channel::unbounded::<PathBuf>();
 
 
@@ -177,9 +298,16 @@ fn main() {
     let global_q = &{
         let global_q = Injector::new();
 
-        // Push our initial directories to search given to use by the user.
-        for x in conf.search_path.iter() {
-            global_q.push(PathBuf::from(x))
+        // Push our initial directories to search given to use by the user, along with any
+        // reference directories they want those compared against. When resuming, the log already
+        // told us what is in them so there is nothing to traverse. These are canonicalized here,
+        // once, so the paths we find under them are canonical up to any symlinks followed along
+        // the way without having to canonicalize every file.
+        if !conf.resume {
+            for x in conf.search_path.iter().chain(conf.ref_path.iter()) {
+                let root = fs::canonicalize(x).unwrap_or_else(|_| x.clone());
+                global_q.push(DirJob::root(root))
+            }
         }
 
         global_q
@@ -227,67 +355,153 @@ fn main() {
         // Only spool up as many jobs as the user request (defaults to 1).
         for _x in 0..conf.jobs {
 
-            // Clone our channel for each thread.
+            // Clone our channels for each thread.
             let tx = tx.clone();
+            let dir_tx = dir_tx.clone();
+            let curr_conf = &conf;
             scope.spawn(move |_| {
 
                 // Create this threads local work queue
-                let mut local_q: Worker<PathBuf> = Worker::new_fifo();
+                let mut local_q: Worker<DirJob> = Worker::new_fifo();
 
                 // Start traversing those directories, grabbing another from the global queue when
                 // finished looking at the current one!
                 loop {
+
+                    // Stop picking up directories once we've been interrupted.
+                    if interrupt::interrupted() {
+                        break
+                    }
+
                     match util::find_task(&mut local_q, &global_q) {
-                        Some(job) => {
+                        Some(mut job) => {
 
-                            // This should be the only case - as only directories will be pushed
-                            // into the global queue and then pulled down into a thread's local
-                            // queue.
-                            if job.is_dir() {
+                            // Only directories are ever pushed into the global queue (the search
+                            // directories were checked up front and the DirEntry tells us which
+                            // entries are directories), so there is no need to stat the job again.
 
-                                // Read contents of dir
-                                let dir_ls = match job.read_dir() {
-                                    Ok(t) => t,
-                                    Err(e) => {
-                                        eprintln!("[Readdir error] {}", e);
-                                        continue
-                                    }
-                                };
-
-                                // Process the contents of the directory
-                                for entry in dir_ls {
-                                    match entry {
-
-                                        // There might be a better way to handle this.
-                                        Ok(curr_ent) => {
-
-                                            // Grab the path from the DirEntry as a PathBuf which
-                                            // we will send down the channel.
-                                            let curr_pb = curr_ent.path();
-
-                                            // Get Path version of our PathBuf so we can check if
-                                            // it's a directory.
-                                            let curr_path = curr_pb.as_path();
-
-                                            // If the Path is a directory push it into global q.
-                                            // Otherwise, assume it's a file and send it down the
-                                            // channel.
-                                            if curr_path.is_dir() {
-                                                global_q.push(curr_pb);
-                                            } else {
-                                                tx.send(curr_pb).unwrap();
+                            // How deep the contents of this directory are, if that's deeper than
+                            // the user wants to look there's no point reading it.
+                            let entry_depth = job.depth + 1;
+
+                            if curr_conf.max_depth.is_some_and(|x| entry_depth > x) {
+                                continue
+                            }
+
+                            // Read contents of dir
+                            let mut dir_ls: Vec<_> = match job.path.read_dir() {
+                                Ok(t) => t.collect(),
+                                Err(e) => {
+                                    eprintln!("[Readdir error] {}", e);
+                                    continue
+                                }
+                            };
+
+                            // Look at everything in name order (readdir order is whatever the
+                            // filesystem feels like), errors go last.
+                            if curr_conf.sort_output {
+                                dir_ls.sort_by(|a, b| match (a, b) {
+                                    (Ok(x), Ok(y)) => x.file_name().cmp(&y.file_name()),
+                                    (a, b) => b.is_ok().cmp(&a.is_ok())
+                                });
+                            }
+
+                            // Any ignore files in here apply to everything in here and below, so
+                            // they need reading before we look at anything else.
+                            if curr_conf.ignore_files {
+                                let entry_names: Vec<OsString> = dir_ls.iter().flatten()
+                                    .map(|x| x.file_name())
+                                    .collect();
+                                job.load_ignores(&entry_names);
+                            }
+
+                            // Keep track of whether there is anything in here at all.
+                            let n_entries = dir_ls.len();
+
+                            // Process the contents of the directory
+                            for entry in dir_ls {
+
+                                match entry {
+
+                                    // There might be a better way to handle this.
+                                    Ok(curr_ent) => {
+
+                                        // Hidden files and directories are skipped on their name
+                                        // alone, if the user doesn't want them.
+                                        if curr_conf.skip_hidden &&
+                                            curr_ent.file_name().to_string_lossy()
+                                                .starts_with('.') {
+                                            continue
+                                        }
+
+                                        // Grab the path from the DirEntry as a PathBuf which
+                                        // we will send down the channel.
+                                        let curr_pb = curr_ent.path();
+
+                                        // The DirEntry already knows what kind of entry this
+                                        // is on most filesystems, so this usually doesn't cost
+                                        // us a stat.
+                                        let curr_type = match curr_ent.file_type() {
+                                            Ok(t) => t,
+                                            Err(e) => {
+                                                eprintln!("[File type error] {}: {}",
+                                                          curr_pb.display(), e);
+                                                continue
                                             }
-                                        },
+                                        };
+
+                                        // Symlinks are followed, so they need a stat to tell
+                                        // whether they lead to a directory, everything else is
+                                        // known without one.
+                                        let is_dir = if curr_type.is_symlink() {
+                                            stats::add(&stats::STAT_CALLS);
+                                            curr_pb.is_dir()
+                                        } else {
+                                            stats::add(&stats::STATS_SAVED);
+                                            curr_type.is_dir()
+                                        };
+
+                                        // Anything matched by the ignore files is skipped,
+                                        // directories along with everything in them.
+                                        if job.is_ignored(&curr_pb, is_dir) {
+                                            continue
+                                        }
 
-                                        // If there was an error with this DirEntry, not sure what
-                                        // we can do beside let the user know and move on.
-                                        Err(e) => {
-                                            eprintln!("Error with DirEntry in dir {:?}: {}",job, e);
+                                        // If it is a directory push it into global q, files (as
+                                        // long as they're deep enough) get sent down the
+                                        // channel, and anything else (FIFOs, sockets and devices)
+                                        // is skipped as there is nothing there to compare.
+                                        // Regular files take their metadata with them, so they
+                                        // don't need to be stat'ed again when examined.
+                                        if is_dir {
+                                            global_q.push(job.child(curr_pb));
+                                        } else if entry_depth < curr_conf.min_depth {
                                             continue
+                                        } else if curr_type.is_symlink() {
+                                            tx.send((curr_pb, None)).unwrap();
+                                        } else if curr_type.is_file() {
+                                            stats::add(&stats::STAT_CALLS);
+                                            tx.send((curr_pb, curr_ent.metadata().ok()))
+                                                .unwrap();
+                                        } else {
+                                            stats::add(&stats::SPECIAL_FILES);
                                         }
+                                    },
+
+                                    // If there was an error with this DirEntry, not sure what
+                                    // we can do beside let the user know and move on.
+                                    Err(e) => {
+                                        eprintln!("Error with DirEntry in dir {:?}: {}", job.path,
+                                                  e);
+                                        continue
                                     }
                                 }
                             }
+
+                            if (n_entries == 0) && curr_conf.empty &&
+                                (job.depth >= curr_conf.min_depth) {
+                                dir_tx.send(job.path).unwrap();
+                            }
                         },
                         None => break,
                     }
@@ -297,9 +511,10 @@ fn main() {
     }).unwrap();
 
     drop(tx);
+    drop(dir_tx);
 
     // Vector to hold all the PathBufs we found while traversing directories
-    let mut f_ls: Vec<PathBuf> = Vec::new();
+    let mut f_ls: Vec<(PathBuf, Option<Metadata>)> = Vec::new();
 
     // Dump the channel contents out into the vec
     for t in rx.iter() {
@@ -308,6 +523,27 @@ fn main() {
 
     drop(rx);
 
+    // Tack on any files the user handed us in a list, these skip traversal and go straight to
+    // being examined like any file we found.
+    if conf.have_files_from {
+        f_ls.extend(util::read_file_list(&conf.files_from).into_iter().map(|x| (x, None)));
+    }
+
+    // Same goes for the files from the log of the run we are resuming.
+    f_ls.extend(resume_ls.drain(..).map(|x| (x, None)));
+
+    // Search and reference directories can overlap (a reference directory inside a search
+    // directory, or the same directory given twice), in which case everything in the overlap was
+    // found once for each. Keep the first of each, or a file would be reported as its own copy.
+    let mut seen_paths = HashSet::new();
+    f_ls.retain(|x| seen_paths.insert(x.0.clone()));
+
+    // The threads finish directories in whatever order they get to them, so put the files back in
+    // path order to examine them in the same order every run.
+    if conf.sort_output {
+        f_ls.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
     // End the directory traversal spinner.
     spin.finish();
 
@@ -340,6 +576,11 @@ fn main() {
     // and pushing that down tx.
     f_ls.par_iter().for_each( |x| {
 
+        // Don't start on any new files once we've been interrupted.
+        if interrupt::interrupted() {
+            return;
+        }
+
         // Counting chickens...
         if !conf.hide_prog {
             pb.inc(1);
@@ -348,14 +589,14 @@ fn main() {
         // Run our process_file function, which will give us back a FileResult struct wrapped in a
         // Some if this file, x, is able to be processed and matches the user's requested extension
         // and file size filters.
-        match util::process_file(x, &conf) {
+        match util::process_file(&x.0, x.1.clone(), &conf) {
             Some(fr) => {
 
                 // If the user wants the log, start logging the files
                 if conf.log {
                     match serde_json::to_string(&fr) {
                         Ok(t) => {
-                            writeln!(&log_file, "{}", t).unwrap();
+                            log_writer.write(t);
                         }
 
                         Err(e) => {
@@ -380,6 +621,19 @@ fn main() {
 
     drop(tx);
 
+    // Mark the end of the file search in the log, which is how a resumed run knows this log has
+    // every file in it. A run interrupted before then can't be resumed from its log, so say so now
+    // rather than leave the user counting on it.
+    if conf.log && !interrupt::interrupted() {
+        log_writer.write(String::from(archive::SEARCH_DONE));
+    } else if conf.log {
+        log_writer.write(String::from(archive::SEARCH_STOPPED));
+        eprintln!("{}", textwrap::fill("DuFF was interrupted before it finished searching for \
+            files, so this run can't be resumed from its log, please start a new run instead. The \
+            hashes calculated so far are still saved in the hash cache (and the archive, if asked \
+            for), so they won't be calculated again.", textwrap::termwidth()));
+    }
+
     // Let the user know we are done with the file examination step and we are now building our
     // tree - which could maybe take some time?
     if !conf.hide_prog {
@@ -396,19 +650,78 @@ fn main() {
     // particular file size encountered and then the corresponding value is a vector of FileResult
     // structs, which will allow us to collate files by their file size making it easy to identify
     // potential duplicates (if the vector length is > 1).
-    let mut dict = HashMap::new();
+    let mut dict: HashMap<u128, Vec<file_result::FileResult>> = HashMap::new();
 
     // Dump the channel contents out into a vec
-    for t in rx.iter() {
+    let mut fr_ls: Vec<file_result::FileResult> = rx.iter().collect();
+
+    // The same file can still turn up under two paths, e.g. a symlink to it (or a symlinked
+    // directory above it) alongside the file itself, or a hard link. The paths can't tell us that
+    // unless every one is canonicalized (and not even then for hard links), so go by the device
+    // and inode and keep the first path, otherwise the file would be reported as a copy of itself
+    // and act could remove the only real copy. An inode of 0 means we don't know it (see
+    // util::file_ids).
+    if conf.sort_output {
+        fr_ls.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    }
+
+    let mut seen_ids = HashSet::new();
+
+    for t in fr_ls.into_iter().filter(|x| (x.inode == 0) || seen_ids.insert((x.dev, x.inode))) {
         // Thanks to this SO answer: https://stackoverflow.com/a/33243862
         dict.entry(t.size).or_insert(Vec::new()).push(t);
     }
 
     drop(rx);
 
+    // The empty files and directories (if the user wants them) are reported as two groups of their
+    // own, so pull the empty files out before they get mixed up with the rest. There's nothing to
+    // hash in them, so they skip hashing altogether.
+    let mut empty_groups: HashMap<String, Vec<file_result::FileResult>> = HashMap::new();
+
+    if conf.empty {
+        if let Some(t) = dict.remove(&0) {
+            empty_groups.insert(String::from("empty_files"), t);
+        }
+
+        let mut empty_dirs = Vec::new();
+        let mut seen_dirs = HashSet::new();
+
+        for x in dir_rx.iter().filter(|x| seen_dirs.insert(x.clone())) {
+            if let Some((mut t, _)) = util::build_result(&x, None, conf.canonicalize) {
+                if conf.detect_types {
+                    t.file_type = String::from("directory");
+                }
+                empty_dirs.push(t);
+            }
+        }
+
+        if !empty_dirs.is_empty() {
+            empty_groups.insert(String::from("empty_dirs"), empty_dirs);
+        }
+    }
+
+    // If the user is after the files that have no copies, any file that doesn't share its size with
+    // another file is unique already, so set those aside before we throw them away below.
+    let mut uniq_ls: Vec<file_result::FileResult> = Vec::new();
+
+    if conf.unique {
+        for v in dict.values() {
+            if v.len() == 1 {
+                uniq_ls.push(v[0].clone());
+            }
+        }
+    }
+
     // Only keep an item in the hashmap if the key's (file size) corresponding value (vector of
-    // FileResult structs) has at least 2 elements (dupes)
-    dict.retain(|_, v| v.len() > 1);
+    // FileResult structs) has at least 2 elements (dupes), see keep_group for how this changes when
+    // comparing against reference directories. When looking for copies of target files, any file
+    // the same size as a target is a candidate, even if it is the only one.
+    if conf.have_target {
+        dict.retain(|k, _| targets.iter().any(|x| x.size == *k));
+    } else {
+        dict.retain(|_, v| util::keep_group(v, &conf));
+    }
 
     // Flatten the hashmap out in this annoying 2-step procedure for further processing. First we
     // dump all of the hashmap values (vectors of FileResult structs) into a single vector, and then
@@ -423,9 +736,21 @@ fn main() {
     // TODO: We need to handle this better, writing out logs and reports if requested, instead of just quitting.
     if n_dupes == 0 {
         println!("No duplicate files!");
-        util::clean_up(&conf);
-        util::write_report(report_file, dict);
-        exit(0)
+
+        // None of the targets can have copies, as nothing else is the size of any of them.
+        if conf.have_target {
+            util::missing_targets(&targets, &HashMap::new(), &conf);
+        }
+
+        stats::finish(&conf, &log_writer);
+        let written_ok = util::clean_up(&mut log_writer, &mut arch_writer);
+
+        if conf.unique {
+            util::write_report(report_file, util::unique_dict(uniq_ls, &conf), &conf);
+        } else {
+            util::write_report(report_file, empty_groups, &conf);
+        }
+        exit(interrupt::exit_code(written_ok))
     }
 
     // Re-init the progress bar with the number of duplicate files.
@@ -454,89 +779,107 @@ fn main() {
             .template("[{elapsed_precise}] [{bar:60}] {pos:>7}/{len:7} ({eta})"));
     }
 
-    // 512 KiB BufReader size
-    let buff_size = 524288;
-
     // Re-init our FileResult channels
     let (tx, rx) =
         crossbeam_channel::unbounded::<file_result::FileResult>();
 
     if conf.log {
-
-        // TODO: Replace unwrap
-        writeln!(log_file, "#Starting hashing\n").unwrap();
+        log_writer.write(String::from("# Starting hashing"));
     }
 
     // Iterate through all FileResult structs in flat using the calc_hash function
     // to calculate a hash, and shove the updated FileResult struct down tx. NB the calc_hash
-    // function updates the internal struct hash value.
-    flat.par_iter_mut().for_each(|x| {
+    // function updates the internal struct hash value. We get back whether each file's hash is
+    // one the hash cache can trust, see below.
+    let cacheable: Vec<bool> = flat.par_iter_mut().map(|x| {
 
         pb.inc(1);
 
+        // Once we've been interrupted don't start hashing anything new, the files that never got a
+        // hash are left out of the report.
+        if interrupt::interrupted() {
+            return false;
+        }
+
         // Indicator to tell downstream code if we found a hash match for this file.
         let mut hash_match_found = false;
 
-        // If we have a user input hash file we should check that first before hashing
-        if conf.archive {
-
-            match  prev_dict.get(&x.size) {
-                Some(t) => {
-
-                    for y in t {
-                        let f_path = &y.file_path;
-                        let f_hash = &y.hash;
-                        let m_time = &y.mtime;
-
-                        // If we have a file match (by size - key, path, and same mtime) grab it's hash
-                        if ((f_path == &x.file_path) & (m_time == &x.mtime)) {
-                            hash_match_found = true;
-                            x.update_hash(f_hash.to_string());
-                            break
-                        }
-
-
-                    }
+        // If we have a user input hash file (or the log of a run we are resuming) we should check
+        // that first before hashing
+        if !prev_dict.is_empty() {
+            if let Some(t) = prev_dict.get(&x.size) {
+                if let Some(h) = util::prev_hash(x, t, &conf) {
+                    hash_match_found = true;
+                    x.update_hash(h);
                 }
-                None =>  (),
-            };
+            }
+        }
 
+        // Whether this file's hash is fit for the hash cache. Hashes from a previous run's files
+        // are only as good as the match that found them (which might just be a name and size), so
+        // only the ones we calculated or took from the cache itself are.
+        let cacheable = !hash_match_found;
 
+        // Next try the hash cache, which only has a match if the file hasn't changed since it was
+        // hashed (same device, inode, size, mtime and ctime).
+        if !hash_match_found {
+            if let Some(h) = hash_cache.get(x) {
+                hash_match_found = true;
+                x.update_hash(h);
+            }
         }
 
         // If we weren't able to find a match for this file then just calculate the hash as normal.
+        // A file we can't read is left out of the results altogether.
         if !hash_match_found {
-            x.calc_hash(buff_size);
+            if let Err(e) = x.calc_hash(buff_size) {
+                eprintln!("[Hashing error] {}: {}", util::escape_path(x.file_path.as_os_str()), e);
+                stats::add(&stats::UNREADABLE_FILES);
+                return false;
+            }
         }
 
         // If the user wants the log, start logging the files
-        if conf.log | conf.archive {
+        if conf.log {
             match serde_json::to_string(&x) {
-                Ok(t) => {
-                    if conf.log {
-                        writeln!(&log_file, "{}", t).unwrap();
-                    }
-                    if conf.archive {
-                        writeln!(&arch_file, "{}", t).unwrap();
-                    }
-                }
+                Ok(t) => log_writer.write(t),
+                Err(e) => eprintln!("[Serialization error] {}", e)
+            }
+        }
 
-                Err(e) => {
-                    eprintln!("[Serialization error] {}", e);
-                }
+        // The archive gets the same, unless the user wants its paths relative to their roots.
+        if conf.archive {
+            let arch_res = if conf.relative_paths {
+                serde_json::to_string(&util::relative_result(x, &roots))
+            } else {
+                serde_json::to_string(&x)
+            };
+
+            match arch_res {
+                Ok(t) => arch_writer.write(t),
+                Err(e) => eprintln!("[Serialization error] {}", e)
             }
         }
 
 
         tx.send(x.to_owned()).unwrap();
 
-    });
+        cacheable
+    }).collect();
 
     // Finish off the file processing progress bar
     pb.finish();
 
+    // Store everything we just hashed (or refresh the entries we used) in the hash cache and write
+    // it back out.
+    for (x, _) in flat.iter().zip(cacheable).filter(|x| x.1) {
+        hash_cache.update(x);
+    }
+
+    hash_cache.save();
+
     // Slight modification of what we did above after the directory walking
-    let mut dict = HashMap::new();
+    let mut dict: HashMap<String, Vec<file_result::FileResult>> = HashMap::new();
 
     drop(tx);
 
@@ -569,8 +912,24 @@ fn main() {
         );
     }
 
-    // Remove any entries from the hashmap that don't have at least 1 duplicate.
-    dict.retain(|_, v| v.len() > 1);
+    // Files that turned out to be the only one with their hash are unique too, grab those before
+    // they are removed.
+    if conf.unique {
+        for v in dict.values() {
+            if v.len() == 1 {
+                uniq_ls.push(v[0].clone());
+            }
+        }
+    }
+
+    // Remove any entries from the hashmap that don't have at least 1 duplicate, or when looking for
+    // copies of target files, swap it out for the locations of each target.
+    if conf.have_target {
+        dict = util::target_dict(&targets, &dict);
+        util::missing_targets(&targets, &dict, &conf);
+    } else {
+        dict.retain(|_, v| util::keep_group(v, &conf));
+    }
 
     // n_dupes counts the total number of duplicate files, whereas n_uniq is the number of unique
     // files that have been duplicated.
@@ -584,9 +943,16 @@ fn main() {
     // TODO: Update this to still write out log files or whatever is needed even if no dupes
     if n_dupes == 0 {
         println!("No duplicate files!");
-        util::clean_up(&conf);
-        util::write_report(report_file, dict);
-        exit(0)
+        stats::finish(&conf, &log_writer);
+        let written_ok = util::clean_up(&mut log_writer, &mut arch_writer);
+
+        if conf.unique {
+            util::write_report(report_file, util::unique_dict(uniq_ls, &conf), &conf);
+        } else {
+            dict.extend(empty_groups);
+            util::write_report(report_file, dict, &conf);
+        }
+        exit(interrupt::exit_code(written_ok))
     }
 
     // Let the user know how many duplicate files we found.
@@ -609,9 +975,9 @@ fn main() {
         );
     }
 
-    // If this function becomes more than just removing a log file if the user didn't request it,
-    // we might need to move the location of this call.
-    util::clean_up(&conf);
+    // Finish off the log and archive, so they are complete before the report shows up.
+    stats::finish(&conf, &log_writer);
+    let written_ok = util::clean_up(&mut log_writer, &mut arch_writer);
 
 
     // Letting the user know we are writing the report and where they can find it again.
@@ -624,10 +990,17 @@ fn main() {
         );
     }
 
-    util::write_report(report_file, dict);
-
+    // In find-unique mode the report lists the files with no copies instead of the duplicates.
+    if conf.unique {
+        util::write_report(report_file, util::unique_dict(uniq_ls, &conf), &conf);
+    } else {
+        dict.extend(empty_groups);
+        util::write_report(report_file, dict, &conf);
+    }
 
+    // The report is still worth having if we were interrupted or the log or archive couldn't be
+    // written, but the exit code should say the run didn't go to plan.
+    if interrupt::exit_code(written_ok) != 0 {
+        exit(interrupt::exit_code(written_ok))
+    }
 }
-
-
-
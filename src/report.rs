@@ -0,0 +1,340 @@
+// This file/module holds the report subcommand (duff report), which converts the tab separated
+// reports DuFF writes into JSON or CSV for other tools (or people) to work with.
+
+// For reading the report and writing out the converted version
+use std::fs;
+use std::io::{self, Write};
+use std::process::exit;
+
+// For writing out JSON
+use serde::{Serialize, Deserialize};
+
+// The report subcommand gets its arguments from clap
+use clap::ArgMatches;
+
+
+// The ReportRow struct holds a single row of a report, i.e. one copy of a duplicated file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportRow {
+
+    // Which group of duplicates this file belongs to, and its number within that group.
+    pub group : u64,
+    pub number : u64,
+
+    // The file's name and the directory it is in.
+    pub name : String,
+    pub path : String,
+
+    // The file's size in bytes and its modified time, as written in the report.
+    pub size : u128,
+    pub mtime : String,
+
+    // Whether the file is in the search ("source") or reference ("reference") directories, only in
+    // reports from runs comparing against reference directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set : Option<String>,
+
+    // The target file this is a copy of, only in reports from runs looking for copies of targets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target : Option<String>,
+
+    // The file's type going by its contents, only in reports from runs detecting file types.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub file_type : Option<String>,
+
+    // The group's id, which stays the same from run to run, missing from reports written before
+    // we started giving groups ids.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id : Option<String>,
+
+    // What duff act should do with this copy ("keep" or "remove"), only there once someone has
+    // marked up the report.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action : Option<String>,
+}
+
+// The run function is the entry point for the report subcommand, reading in the report and writing
+// it back out in the format the user asked for.
+// Arguments are as follows:
+// in_args: The ArgMatches for the report subcommand.
+pub fn run(in_args: &ArgMatches) {
+
+    // report is required and format has a default, so clap will have given us something.
+    let rep_str = in_args.value_of("report").unwrap();
+    let format = in_args.value_of("format").unwrap();
+
+    let (rows, incomplete) = read_report(rep_str);
+
+    if incomplete {
+        eprintln!("Warning: {} is from a run that was interrupted, so it is incomplete.", rep_str);
+    }
+
+    let out_str = match format {
+        "csv" => to_csv(&rows),
+
+        // Our rows are just strings and numbers, so they will always serialize.
+        _ => format!("{}\n", serde_json::to_string_pretty(&rows).unwrap())
+    };
+
+    let write_res = match in_args.value_of("out") {
+        Some(t) => fs::write(t, out_str),
+        None => io::stdout().write_all(out_str.as_bytes())
+    };
+
+    if let Err(e) = write_res {
+        eprintln!("Error writing converted report.\nError text: {}", e);
+        exit(1)
+    }
+}
+
+// The read_report function reads in the report at rep_str, returning its rows along with whether
+// it was marked as incomplete. Any line we can't make sense of gets the file and line number sent
+// to stderr and we exit.
+// Arguments are as follows:
+// rep_str: Path of the report to read.
+pub fn read_report(rep_str: &str) -> (Vec<ReportRow>, bool) {
+    let rep_content = match fs::read_to_string(rep_str) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Error reading report {}.\nError text: {}", rep_str, e);
+            exit(1)
+        }
+    };
+
+    let mut rows = Vec::new();
+    let mut incomplete = false;
+
+    // Where the optional columns (Set, Target, Type, Group ID and Action) are, if they are there at
+    // all.
+    let mut header_cols: Vec<&str> = Vec::new();
+
+    for (line_num, curr_line) in (1..).zip(rep_content.lines()) {
+        if curr_line.starts_with("# INCOMPLETE") {
+            incomplete = true;
+            continue
+        }
+
+        if curr_line.starts_with('#') || curr_line.is_empty() {
+            continue
+        }
+
+        let cols: Vec<&str> = curr_line.split('\t').collect();
+
+        if cols[0] == "File Count" {
+            header_cols = cols;
+            continue
+        }
+
+        let (group, number, size) = match (cols[0].parse(), cols.get(1).map(|x| x.parse()),
+                                           cols.get(4).map(|x| x.parse())) {
+            (Ok(g), Some(Ok(n)), Some(Ok(s))) if cols.len() >= 6 => (g, n, s),
+            _ => {
+                eprintln!("Error reading {} at line {}: not a DuFF report row.", rep_str,
+                          line_num);
+                exit(1)
+            }
+        };
+
+        let extra = |name: &str| header_cols.iter().position(|x| *x == name)
+            .and_then(|x| cols.get(x))
+            .map(|x| x.to_string());
+
+        rows.push(ReportRow {
+            group : group,
+            number : number,
+            name : cols[2].to_string(),
+            path : cols[3].to_string(),
+            size : size,
+            mtime : cols[5].to_string(),
+            set : extra("Set"),
+            target : extra("Target"),
+            file_type : extra("Type"),
+            group_id : extra("Group ID"),
+            action : extra("Action"),
+        });
+    }
+
+    (rows, incomplete)
+}
+
+// The to_csv function turns the report rows into CSV, with a header row.
+fn to_csv(rows: &[ReportRow]) -> String {
+    let have_set = rows.iter().any(|x| x.set.is_some());
+    let have_target = rows.iter().any(|x| x.target.is_some());
+    let have_type = rows.iter().any(|x| x.file_type.is_some());
+    let have_id = rows.iter().any(|x| x.group_id.is_some());
+    let have_action = rows.iter().any(|x| x.action.is_some());
+
+    let mut out_str = String::from("group,number,name,path,size,mtime");
+
+    if have_set {
+        out_str.push_str(",set");
+    }
+    if have_target {
+        out_str.push_str(",target");
+    }
+    if have_type {
+        out_str.push_str(",type");
+    }
+    if have_id {
+        out_str.push_str(",group_id");
+    }
+    if have_action {
+        out_str.push_str(",action");
+    }
+    out_str.push('\n');
+
+    for x in rows.iter() {
+        let mut fields = vec![x.group.to_string(), x.number.to_string(), csv_field(&x.name),
+                              csv_field(&x.path), x.size.to_string(), csv_field(&x.mtime)];
+
+        if have_set {
+            fields.push(csv_field(x.set.as_deref().unwrap_or("")));
+        }
+        if have_target {
+            fields.push(csv_field(x.target.as_deref().unwrap_or("")));
+        }
+        if have_type {
+            fields.push(csv_field(x.file_type.as_deref().unwrap_or("")));
+        }
+        if have_id {
+            fields.push(csv_field(x.group_id.as_deref().unwrap_or("")));
+        }
+        if have_action {
+            fields.push(csv_field(x.action.as_deref().unwrap_or("")));
+        }
+
+        out_str.push_str(&fields.join(","));
+        out_str.push('\n');
+    }
+
+    out_str
+}
+
+// The csv_field function quotes a field if it has anything in it that would otherwise break up the
+// CSV (commas, quotes or newlines), doubling up any quotes inside it.
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// The read_any_report function reads in a report in any of the formats we deal in, the tab
+// separated reports DuFF writes or the JSON and CSV versions duff report writes (which may well
+// have been edited by hand since), returning its rows along with whether it was marked as
+// incomplete. JSON is picked out by its opening [ and CSV by its header row.
+// Arguments are as follows:
+// rep_str: Path of the report to read.
+pub fn read_any_report(rep_str: &str) -> (Vec<ReportRow>, bool) {
+    let rep_content = match fs::read_to_string(rep_str) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Error reading report {}.\nError text: {}", rep_str, e);
+            exit(1)
+        }
+    };
+
+    let trimmed = rep_content.trim_start();
+
+    if trimmed.starts_with('[') {
+        match serde_json::from_str(trimmed) {
+            Ok(t) => (t, false),
+            Err(e) => {
+                eprintln!("Error reading JSON report {}: {}", rep_str, e);
+                exit(1)
+            }
+        }
+    } else if trimmed.starts_with("group,") {
+        (read_csv(rep_str, trimmed), false)
+    } else {
+        read_report(rep_str)
+    }
+}
+
+// The read_csv function turns the CSV written by to_csv (and whatever a spreadsheet makes of it)
+// back into report rows, finding the columns by the names in the header row. Any row we can't
+// make sense of gets the file and row number sent to stderr and we exit.
+// Arguments are as follows:
+// rep_str: Path of the report, for error messages.
+// rep_content: The CSV itself.
+fn read_csv(rep_str: &str, rep_content: &str) -> Vec<ReportRow> {
+    let mut records = split_csv(rep_content).into_iter();
+    let header = records.next().unwrap_or_default();
+    let mut rows = Vec::new();
+
+    for (row_num, cols) in (1..).zip(records) {
+        let field = |name: &str| header.iter().position(|x| x == name)
+            .and_then(|x| cols.get(x))
+            .filter(|x| !x.is_empty())
+            .cloned();
+
+        let num_field = |name: &str| field(name).and_then(|x| x.parse::<u128>().ok());
+
+        let (group, number, size) = match (num_field("group"), num_field("number"),
+                                           num_field("size")) {
+            (Some(g), Some(n), Some(s)) => (g as u64, n as u64, s),
+            _ => {
+                eprintln!("Error reading {} at row {}: not a DuFF report row.", rep_str, row_num);
+                exit(1)
+            }
+        };
+
+        rows.push(ReportRow {
+            group : group,
+            number : number,
+            name : field("name").unwrap_or_default(),
+            path : field("path").unwrap_or_default(),
+            size : size,
+            mtime : field("mtime").unwrap_or_default(),
+            set : field("set"),
+            target : field("target"),
+            file_type : field("type"),
+            group_id : field("group_id"),
+            action : field("action"),
+        });
+    }
+
+    rows
+}
+
+// The split_csv function splits CSV up into records and fields, undoing the quoting csv_field does
+// (quoted fields can hold commas, doubled up quotes and newlines). Blank lines are skipped.
+fn split_csv(rep_content: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = rep_content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => (),
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+
+                if record.iter().any(|x| !x.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                } else {
+                    record.clear();
+                }
+            }
+            _ => field.push(c)
+        }
+    }
+
+    record.push(field);
+
+    if record.iter().any(|x| !x.is_empty()) {
+        records.push(record);
+    }
+
+    records
+}
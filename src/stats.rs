@@ -0,0 +1,67 @@
+// This file/module keeps count of the things DuFF skipped along the way (special files like FIFOs
+// and sockets, files it couldn't read), so the user gets a tally at the end of the run rather than
+// having to dig through stderr, along with how many metadata lookups it made and saved, as on
+// filesystems like Lustre those are where the time goes. The counters are shared by every worker
+// thread.
+
+// The counters themselves
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// For printing the summary and adding it to the log
+use crate::config::Config;
+use crate::writer::RecordWriter;
+
+
+// FIFOs, sockets and device nodes found while searching, which are never examined (opening a FIFO
+// to hash it could block forever).
+pub static SPECIAL_FILES: AtomicU64 = AtomicU64::new(0);
+
+// Files we couldn't open, usually down to permissions.
+pub static UNREADABLE_FILES: AtomicU64 = AtomicU64::new(0);
+
+// Metadata lookups (stats) made for entries and files.
+pub static STAT_CALLS: AtomicU64 = AtomicU64::new(0);
+
+// Metadata lookups we didn't need to make, because the DirEntry already told us what an entry is or
+// its metadata was carried forward from traversal.
+pub static STATS_SAVED: AtomicU64 = AtomicU64::new(0);
+
+// Paths we didn't canonicalize (each of which would have cost a lstat or readlink per component),
+// as the user didn't ask for it.
+pub static CANON_SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+// The add function bumps counter up by one.
+pub fn add(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+// The summary function returns a line for each counter that isn't zero, in the same layout as the
+// overview.
+pub fn summary() -> Vec<String> {
+    let counters = [("Special Files Skipped:", &SPECIAL_FILES),
+                    ("Unreadable Files Skipped:", &UNREADABLE_FILES),
+                    ("Metadata Lookups:", &STAT_CALLS),
+                    ("Metadata Lookups Saved:", &STATS_SAVED),
+                    ("Path Canonicalizations Skipped:", &CANON_SKIPPED)];
+
+    counters.iter()
+        .map(|(name, counter)| (name, counter.load(Ordering::Relaxed)))
+        .filter(|(_, n)| *n > 0)
+        .map(|(name, n)| format!("{:<40} {:>1}", name, n))
+        .collect()
+}
+
+// The finish function shows the user the summary (unless they want silence) and adds it to the
+// log as comments, so it needs to be called before the log is finished off.
+// Arguments are as follows:
+// curr_conf: The DuFF config.
+// log_writer: The writer for the log file.
+pub fn finish(curr_conf: &Config, log_writer: &RecordWriter) {
+    for x in summary() {
+        if !curr_conf.silent {
+            println!("{}", x);
+        }
+
+        log_writer.write(format!("# {}", x));
+    }
+}
@@ -8,25 +8,50 @@
 use crate::config::Config;
 use crate::file_result;
 
-// For our datetime helper functions (dt and f_dt)
-use chrono::{DateTime, Utc};
+// For detecting file types in process_file
+use crate::file_type;
 
-// To create files for output (open_file) and clean up any they don't want to keep.
-use std::fs::{File, remove_file};
+// For counting the special and unreadable files process_file skips
+use crate::stats;
+
+// For our datetime helper functions (dt and f_dt), and reading in dates (parse_time)
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// To create files for output (open_file)
+use std::fs::{File, Metadata};
+
+// For the device, inode and ctime of files (file_ids)
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 
 // Paths are taken as input to 3 functions (open_file, check_ext, process_file)
 use std::path::{Path, PathBuf};
 
-// For writing out our report file.
-use std::io::Write;
+// For paths that aren't valid UTF-8 (escape_path, os_bytes, os_from_bytes)
+use std::ffi::{OsStr, OsString};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+// For writing out our report file, and reading in file lists.
+use std::io::{self, Read, Write};
 
 // For printing out report
 use std::collections::HashMap;
+use std::fmt::Display;
+
+// For group ids of groups that weren't hashed
+use twox_hash::xxh3;
 
 // For the find task function
 use crossbeam_deque::{Injector, Worker, Steal};
 use crate::file_result::FileResult;
 
+// For finishing off the log and archive (clean_up)
+use crate::writer::RecordWriter;
+
+// To mark the report as incomplete if we were interrupted
+use crate::interrupt;
+
 
 // Extract some info from our manifest file to be used at different places for output to user.
 pub const PROG_NAME: &'static str = env!("CARGO_PKG_NAME");
@@ -49,6 +74,29 @@ pub fn f_dt() -> String {
 }
 
 
+// The host_name function returns the name of the machine we are running on, which gets recorded in
+// archive and log headers. We try the kernel's idea of it first, then the usual environment
+// variables, and give up with "unknown".
+pub fn host_name() -> String {
+    for x in ["/proc/sys/kernel/hostname", "/etc/hostname"].iter() {
+        if let Ok(t) = std::fs::read_to_string(x) {
+            if !t.trim().is_empty() {
+                return t.trim().to_string();
+            }
+        }
+    }
+
+    for x in ["HOSTNAME", "COMPUTERNAME"].iter() {
+        if let Ok(t) = std::env::var(x) {
+            if !t.is_empty() {
+                return t;
+            }
+        }
+    }
+
+    String::from("unknown")
+}
+
 // The open_file function is a helper function that simply creates a file and returns that created
 // file to the caller.
 // Arguments are as follows:
@@ -89,36 +137,93 @@ pub fn open_file(file_str: &String, out_dir: &String, user_dir: bool) -> File {
 }
 
 // The check_ext function checks to see if the current file has an extension that matches one
-// specified by the user or not and returns a bool indicating the result.
+// specified by the user or not and returns a bool indicating the result. The default "*" matches
+// everything, otherwise see has_ext.
 // Arguments are as follows:
 // curr_file: The current file to check
-// curr_exts: The list of extensions we should check for.
+// curr_exts: The list of extensions we should check for, as cleaned up by clean_ext.
 pub fn check_ext(curr_file: &Path, curr_exts: &Vec<String>) -> bool {
 
     // If we only have 1 extension and that extension is the default asterisk, go ahead and return
     // true
     if ((curr_exts.len() == 1) && (curr_exts[0] == "*")) {
         return true;
-    } else {
+    }
+
+    has_ext(curr_file, curr_exts)
+}
 
-        // Get a string version of the filename
-        // TODO: Add functionality to replace these 2 unwraps!
-        let clean_fn = curr_file.file_name().unwrap().to_str().unwrap().to_string();
+// The has_ext function checks whether the current file's name ends in one of curr_exts, ignoring
+// case, returning a bool indicating the result. The extension has to start right after a dot that
+// isn't the first character of the name, so bam matches x.bam and x.BAM but not notabam or .bam,
+// and compound extensions like fastq.gz work the same way.
+// Arguments are as follows:
+// curr_file: The current file to check
+// curr_exts: The list of extensions we should check for, as cleaned up by clean_ext.
+pub fn has_ext(curr_file: &Path, curr_exts: &[String]) -> bool {
+
+    // Get a lowercase string version of the filename, anything that isn't valid UTF-8 can't match
+    // an extension the user typed anyway, so a lossy conversion is fine here.
+    let clean_fn = match curr_file.file_name() {
+        Some(t) => t.to_string_lossy().to_lowercase(),
+        None => return false
+    };
 
-        // Loop over the extensions list and see if our file (curr_file) ends with one of the
-        // extensions.  Theoretically, this way of dealing with extensions should mean the user
-        // does not have to (but still can) include dots prior to the extension, although they must
-        // include the dots if they are inside an extension, like a .fastq.gz.
-        for x in curr_exts.iter() {
+    curr_exts.iter().any(|x| {
+        (clean_fn.len() > x.len() + 1) && clean_fn.ends_with(x.as_str()) &&
+            clean_fn[..clean_fn.len() - x.len()].ends_with('.')
+    })
+}
 
-            // If there is match return true immediately
-            if clean_fn.ends_with(x) {
-                return true;
-            }
+// The clean_ext function puts an extension from the user into the form has_ext expects, lowercase
+// and without any leading dots, so .BAM, bam and BAM are all the same extension.
+pub fn clean_ext(ext_str: &str) -> String {
+    ext_str.trim().trim_start_matches('.').to_lowercase()
+}
+
+// The split_list function splits a comma separated list from the user, treating \, as a comma
+// that is part of an entry rather than a separator. Empty entries are dropped.
+// Arguments are as follows:
+// list_str: The comma separated list.
+pub fn split_list(list_str: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut curr_entry = String::new();
+    let mut chars = list_str.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if (c == '\\') && (chars.peek() == Some(&',')) {
+            curr_entry.push(',');
+            chars.next();
+        } else if c == ',' {
+            entries.push(std::mem::take(&mut curr_entry));
+        } else {
+            curr_entry.push(c);
         }
+    }
+    entries.push(curr_entry);
+
+    entries.into_iter().filter(|x| !x.is_empty()).collect()
+}
+
+// The split_paths function works like split_list for lists of directories (or files), except a
+// value that is an existing path as is is never split, so names with commas in them just work.
+// Arguments are as follows:
+// list_str: The path, or comma separated list of paths.
+pub fn split_paths(list_str: &str) -> Vec<String> {
+    if Path::new(list_str).exists() {
+        vec![list_str.to_string()]
+    } else {
+        split_list(list_str)
+    }
+}
 
-        // If no extension match was found, return false.
-        return false;
+// The split_os_paths function does the same as split_paths for paths from the command line, which
+// don't have to be valid UTF-8. One that isn't can't be a list we know how to split, so it is
+// taken as it is.
+pub fn split_os_paths(list_str: &OsStr) -> Vec<PathBuf> {
+    match list_str.to_str() {
+        Some(t) => split_paths(t).into_iter().map(PathBuf::from).collect(),
+        None => vec![PathBuf::from(list_str)]
     }
 }
 
@@ -140,6 +245,130 @@ pub fn check_size(curr_fs: u128, min_size: u128, max_size: u128) -> bool {
 }
 
 
+// The check_time function checks whether a file's mtime falls inside the window the user asked for
+// with --newer-than and --older-than, returning a bool indicating whether or not it does. Either
+// end of the window can be left open (None).
+// Arguments are as follows:
+// mtime: The mtime of whatever file being checked.
+// newer_than: The mtime has to be after this, if given.
+// older_than: The mtime has to be before this, if given.
+pub fn check_time(mtime: DateTime<Utc>, newer_than: Option<DateTime<Utc>>,
+                  older_than: Option<DateTime<Utc>>) -> bool {
+    newer_than.is_none_or(|x| mtime > x) && older_than.is_none_or(|x| mtime < x)
+}
+
+// The check_owner function checks whether a file is owned by one of the users and one of the groups
+// the user asked for, returning a bool indicating the result. An empty list means any owner (or
+// group) will do.
+// Arguments are as follows:
+// uid: The user id owning the file.
+// gid: The group id the file belongs to.
+// owner_uids: The user ids the user wants, from --user and --uid.
+// owner_gids: The group ids the user wants, from --group.
+pub fn check_owner(uid: u32, gid: u32, owner_uids: &[u32], owner_gids: &[u32]) -> bool {
+    (owner_uids.is_empty() || owner_uids.contains(&uid)) &&
+        (owner_gids.is_empty() || owner_gids.contains(&gid))
+}
+
+// The check_perm function checks a file's permission bits against the --perm filter, the same way
+// find -perm does: '=' needs exactly these bits, '-' needs all of them set and '/' any of them (or
+// none at all if no bits were given).
+// Arguments are as follows:
+// mode: The file's mode, as it comes from its metadata.
+// perm: The filter, as parsed by parse_perm.
+pub fn check_perm(mode: u32, perm: (char, u32)) -> bool {
+    let mode = mode & 0o7777;
+
+    match perm {
+        ('-', bits) => (mode & bits) == bits,
+        ('/', bits) => (bits == 0) || ((mode & bits) != 0),
+        (_, bits) => mode == bits
+    }
+}
+
+// The parse_perm function turns the user's --perm value (644, -022 or /022) into the way it should
+// be matched and the permission bits, returning None if it doesn't make sense.
+pub fn parse_perm(perm_str: &str) -> Option<(char, u32)> {
+    let (how, bits_str) = match perm_str.chars().next() {
+        Some(t) if (t == '-') || (t == '/') => (t, &perm_str[1..]),
+        _ => ('=', perm_str)
+    };
+
+    match u32::from_str_radix(bits_str, 8) {
+        Ok(t) if t <= 0o7777 => Some((how, t)),
+        _ => None
+    }
+}
+
+// The parse_time function turns the user's --newer-than or --older-than value into a point in
+// time, returning None if it doesn't make sense. Ages (a number followed by s, m, h, d, w or y,
+// where a year is 365 days) are counted back from now, dates and times are taken in the local
+// timezone, and RFC 3339 timestamps are taken as they are.
+// Arguments are as follows:
+// time_str: The date or age the user gave us.
+// now: The time ages are counted back from, normally the start of the run.
+pub fn parse_time(time_str: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let time_str = time_str.trim();
+
+    if let Some(unit) = time_str.chars().last() {
+        let secs = match unit {
+            's' => Some(1),
+            'm' => Some(60),
+            'h' => Some(3600),
+            'd' => Some(86400),
+            'w' => Some(7 * 86400),
+            'y' => Some(365 * 86400),
+            _ => None
+        };
+
+        if let Some(t) = secs {
+            return time_str[..time_str.len() - 1].parse::<i64>().ok()
+                .and_then(|x| x.checked_mul(t))
+                .map(|x| now - chrono::Duration::seconds(x));
+        }
+    }
+
+    if let Ok(t) = DateTime::parse_from_rfc3339(time_str) {
+        return Some(t.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"].iter()
+        .find_map(|x| NaiveDateTime::parse_from_str(time_str, x).ok())
+        .or_else(|| NaiveDate::parse_from_str(time_str, "%Y-%m-%d").ok().map(|x| x.and_hms(0, 0, 0)))?;
+
+    Local.from_local_datetime(&naive).earliest().map(|x| x.with_timezone(&Utc))
+}
+
+// The read_file_list function reads in a list of file paths, either from the file at list_str or
+// from stdin if list_str is "-", and returns them as PathBufs. The list can either be newline
+// separated or NUL separated (i.e. find -print0), if there are any NUL bytes at all we assume the
+// latter. Empty entries are skipped, and if the list can't be read we let the user know and exit.
+// Arguments are as follows:
+// list_str: Path to the file list, or "-" for stdin.
+pub fn read_file_list(list_str: &str) -> Vec<PathBuf> {
+    let mut list_bytes = Vec::new();
+
+    let read_res = if list_str == "-" {
+        io::stdin().read_to_end(&mut list_bytes)
+    } else {
+        File::open(list_str).and_then(|mut f| f.read_to_end(&mut list_bytes))
+    };
+
+    if let Err(e) = read_res {
+        eprintln!("[Error reading file list] {}", e);
+        println!("Error reading input file list {}. \nPlease fix and try to re-run.", list_str);
+        std::process::exit(1);
+    }
+
+    let sep = if list_bytes.contains(&0) { b'\0' } else { b'\n' };
+
+    list_bytes.split(|x| *x == sep)
+        .map(|x| x.strip_suffix(b"\r").unwrap_or(x))
+        .filter(|x| !x.is_empty())
+        .map(|x| PathBuf::from(os_from_bytes(x.to_vec())))
+        .collect()
+}
+
 // The find_task function is "adapted" from Crossbeam's deque docs
 // [https://docs.rs/crossbeam/0.7.1/crossbeam/deque/index.html] and from Ken Sternberg's Parallel
 // Boggle Solver cited above.
@@ -159,15 +388,28 @@ pub fn find_task<T>(local: &mut Worker<T>, global: &Injector<T>) -> Option<T> {
 
 // This function does all the processing of a PathBuf. Specifically, it collects the metadata
 // (filesize and mtime) and will create a new FileResult object which it will return wrapped in a
-// Some, otherwise if this function hits an error or the path in question doesn't satisfy the
-// extension or file size requirements None is returned.
-pub fn process_file(curr_pb: &PathBuf, curr_conf: &Config) -> Option<file_result::FileResult> {
-
-    // Convert pathbuf to just path
-    let mut curr_path = curr_pb.as_path();
+// Some, along with the metadata itself for the filters that need more than the FileResult holds,
+// otherwise if this function hits an error None is returned. No filtering happens here, see
+// process_file for that.
+// Arguments are as follows:
+// curr_pb: The path to the file.
+// curr_meta: The file's metadata if the caller already has it (from the DirEntry during traversal),
+//            saving us a stat, otherwise None and we look it up ourselves.
+// canon: Whether to canonicalize the path (resolving symlinks, which costs a lstat or readlink for
+//        every component), otherwise the path is just made absolute.
+pub fn build_result(curr_pb: &Path, curr_meta: Option<Metadata>, canon: bool)
+                    -> Option<(file_result::FileResult, Metadata)> {
+
+    // Canonicalize the path if asked to, otherwise making it absolute is enough (and free) as the
+    // directories we search are canonicalized before traversal starts.
+    let path_res = if canon {
+        curr_pb.canonicalize()
+    } else {
+        stats::add(&stats::CANON_SKIPPED);
+        std::path::absolute(curr_pb)
+    };
 
-    // And canonicalize the path
-    let canon_path = match curr_path.canonicalize() {
+    let canon_path = match path_res {
         Ok(u) => u,
         Err(e) => {
             eprintln!("Error canonicalizing path!\n{}", e);
@@ -176,65 +418,44 @@ pub fn process_file(curr_pb: &PathBuf, curr_conf: &Config) -> Option<file_result
     };
 
     // Extract the path version
-    curr_path = canon_path.as_path();
+    let curr_path = canon_path.as_path();
 
-    // Now try to extract the file name as OsStr and then convert that to actual str
-    let name_os = match curr_path.file_name() {
-        Some(u) => u,
+    // Now try to extract the file name, kept as an OsString so names that aren't valid UTF-8
+    // make it through too
+    let file_name = match curr_path.file_name() {
+        Some(u) => u.to_os_string(),
         None => {
             eprintln!("Error extracting file name from path.");
             return None;
         }
     };
 
-    // Convert the OsStr to str
-    let name_str = match name_os.to_str() {
-        Some(u) => u,
-        None => {
-            eprintln!("Error converting file name OsStr to str.");
-            return None;
-        }
-    };
-
-    let file_name = String::from(name_str);
-
     // Get the path to this file
     let dir_path = match canon_path.parent() {
-        Some(u) => u,
+        Some(u) => u.to_path_buf(),
         None => {
             eprintln!("Error extracting directory path!");
             return None;
         }
     };
 
-    // Capture this path as a str
-    let dir_str = match dir_path.to_str() {
-        Some(u) => u,
-        None => {
-            eprintln!("Error converting path to string.");
-            return None;
+    // Use the metadata we were handed if there is any, otherwise get the metadata for this file so
+    // we can access m-time and file size
+    let curr_meta = match curr_meta {
+        Some(u) => {
+            stats::add(&stats::STATS_SAVED);
+            u
         }
-    };
-
-    let dir_str = String::from(dir_str);
-
-    // Capture this path as a str
-    let path_str = match curr_path.to_str() {
-        Some(u) => u,
         None => {
-            eprintln!("Error converting path to string.");
-            return None;
-        }
-    };
-
-    let path_str = String::from(path_str);
-
-    // Attempt to get the metadata for this file so we can access m-time and file size
-    let curr_meta = match curr_path.metadata() {
-        Ok(u) => u,
-        Err(e) => {
-            eprintln!("Error collecting metadata. {}", e);
-            return None;
+            stats::add(&stats::STAT_CALLS);
+
+            match curr_path.metadata() {
+                Ok(u) => u,
+                Err(e) => {
+                    eprintln!("Error collecting metadata. {}", e);
+                    return None;
+                }
+            }
         }
     };
 
@@ -252,95 +473,531 @@ pub fn process_file(curr_pb: &PathBuf, curr_conf: &Config) -> Option<file_result
     // Grab the file size
     let fs = u128::from(curr_meta.len());
 
-    // Skip files of size 0
-    if fs == 0 {
+    // And the device, inode and ctime used to key the hash cache
+    let (dev, inode, ctime) = file_ids(&curr_meta);
+
+    return Some((file_result::FileResult::new(file_name, dir_path, canon_path.clone(), fs, mtime,
+                                              dev, inode, ctime), curr_meta));
+}
+
+// The file_ids function pulls the device number, inode number and ctime (in nanoseconds) out of a
+// file's metadata. These only exist on unix-like systems, everywhere else we hand back zeros, which
+// the hash cache treats as "don't cache this file".
+#[cfg(unix)]
+pub fn file_ids(curr_meta: &Metadata) -> (u64, u64, i64) {
+    (curr_meta.dev(), curr_meta.ino(),
+     curr_meta.ctime().saturating_mul(1_000_000_000).saturating_add(curr_meta.ctime_nsec()))
+}
+
+#[cfg(not(unix))]
+pub fn file_ids(_curr_meta: &Metadata) -> (u64, u64, i64) {
+    (0, 0, 0)
+}
+
+// The file_owner function pulls the owning user id, group id and mode (permission bits) out of a
+// file's metadata. Like file_ids these only exist on unix-like systems, and the ownership and
+// permission filters can't be used anywhere else.
+#[cfg(unix)]
+pub fn file_owner(curr_meta: &Metadata) -> (u32, u32, u32) {
+    (curr_meta.uid(), curr_meta.gid(), curr_meta.mode())
+}
+
+#[cfg(not(unix))]
+pub fn file_owner(_curr_meta: &Metadata) -> (u32, u32, u32) {
+    (0, 0, 0)
+}
+
+// This function takes a PathBuf found during directory traversal, builds a FileResult for it (see
+// build_result) and checks it against the user's requirements. The FileResult is returned wrapped
+// in a Some, otherwise if there was an error or the file doesn't satisfy the extension, file size,
+// mtime, ownership, permission or file type requirements None is returned.
+// Arguments are as follows:
+// curr_pb: The path to the file.
+// curr_meta: The file's metadata if traversal already got it, see build_result.
+// curr_conf: The DuFF config.
+pub fn process_file(curr_pb: &Path, curr_meta: Option<Metadata>, curr_conf: &Config)
+                    -> Option<file_result::FileResult> {
+
+    let (mut curr_fr, curr_meta) = build_result(curr_pb, curr_meta, curr_conf.canonicalize)?;
+
+    // Only regular files (or symlinks to them) are worth comparing. Directories can turn up here
+    // in file lists, and anything else (FIFOs, sockets and devices) is skipped and counted, as
+    // opening a FIFO to hash it could block forever.
+    if !curr_meta.is_file() {
+        if !curr_meta.is_dir() {
+            stats::add(&stats::SPECIAL_FILES);
+        }
+        return None
+    }
+
+    // Skip files of size 0, unless the user wants them reported as a group of their own
+    if (curr_fr.size == 0) && !curr_conf.empty {
         return None
     }
 
     // Run our extension and size matching checks based on user's input
-    let ext_match = check_ext(curr_path, &curr_conf.exts);
-    let size_match = check_size(fs,
+    let ext_match = check_ext(&curr_fr.file_path, &curr_conf.exts) &&
+        !has_ext(&curr_fr.file_path, &curr_conf.exclude_exts);
+    let size_match = check_size(curr_fr.size,
                                       curr_conf.ll_size,
                                       curr_conf.ul_size);
+    let time_match = check_time(curr_fr.mtime, curr_conf.newer_than, curr_conf.older_than);
+
+    let (uid, gid, mode) = file_owner(&curr_meta);
+    let owner_match = check_owner(uid, gid, &curr_conf.owner_uids, &curr_conf.owner_gids);
+    let perm_match = curr_conf.perm.is_none_or(|x| check_perm(mode, x));
 
     // As long as this file fits the user's requirements return a FileResult struct, if not just
     // return a None.
-    if ext_match && size_match {
-        return Some(file_result::FileResult::new(file_name, dir_str,path_str, fs, mtime));
+    if !(ext_match && size_match && time_match && owner_match && perm_match) {
+        return None;
+    }
+
+    // Make sure we can actually read the file now, so permission problems are reported here
+    // rather than turning up part way through hashing.
+    if let Err(e) = File::open(&curr_fr.file_path) {
+        eprintln!("[Unreadable file] {}: {}", escape_path(curr_fr.file_path.as_os_str()), e);
+        stats::add(&stats::UNREADABLE_FILES);
+        return None
+    }
+
+    // Only now that everything else has passed do we go to the trouble of opening the file to see
+    // what it really is.
+    if curr_conf.detect_types {
+        curr_fr.file_type = file_type::detect(&curr_fr.file_path);
+
+        if !curr_conf.file_types.is_empty() && !curr_conf.file_types.contains(&curr_fr.file_type) {
+            return None;
+        }
     }
 
-    return None
+    Some(curr_fr)
 }
 
-// This function does all the end of processing cleaning up.  Saving some files if the user wanted
-// them and deleting them if they didn't.
-pub fn clean_up(curr_conf: &Config)  {
+// The map_path function rewrites curr_path using the user's path maps (--path-map OLD=NEW), so
+// paths recorded where the storage was mounted under a different prefix line up with ours. The
+// longest OLD prefix that matches on whole path components wins, paths without a match are
+// returned unchanged.
+// Arguments are as follows:
+// curr_path: The path to rewrite.
+// path_map: The (OLD, NEW) prefix pairs the user gave us.
+pub fn map_path(curr_path: &Path, path_map: &[(String, String)]) -> PathBuf {
+    let mut best: Option<(&Path, &String)> = None;
 
-    // Remove the log if they never asked for a log (log bool == False)
-    if !curr_conf.log {
-        let log_path = Path::new(&curr_conf.log_file);
+    for (old, new) in path_map.iter() {
+        let old_path = Path::new(old);
 
-        match remove_file(log_path) {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("Error while trying to remove log file {}.\nError text: {}",
-                           &curr_conf.log_file, e);
-            }
+        if curr_path.starts_with(old_path) &&
+            best.is_none_or(|x| old_path.as_os_str().len() > x.0.as_os_str().len()) {
+            best = Some((old_path, new));
         }
     }
 
-    // Remove the archive if they never asked for an archive (archive bool == False)
-    if !curr_conf.archive {
-        let arch_path = Path::new(&curr_conf.archive_file);
+    match best {
+        Some((old, new)) => {
+
+            // starts_with already told us this prefix is there
+            let rest = curr_path.strip_prefix(old).unwrap();
 
-        match remove_file(arch_path) {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("Error while trying to remove log file {}.\nError text: {}",
-                          &curr_conf.archive_file, e);
+            if rest.as_os_str().is_empty() {
+                PathBuf::from(new)
+            } else {
+                Path::new(new).join(rest)
+            }
+        }
+        None => curr_path.to_path_buf()
+    }
+}
+
+// The escape_path function gives a printable version of curr_path for reports and messages. Paths
+// that are valid UTF-8 come back unchanged, except that a backslash is doubled and tabs, newlines
+// and carriage returns (which would break up a report's rows and columns) are written as \t, \n
+// and \r, and any bytes that aren't are written as \xNN escapes. As a literal \xNN in a name
+// prints as \\xNN, two different paths never print the same, and unescape_path gets them back.
+// Arguments are as follows:
+// curr_path: The path (or file name) to print.
+pub fn escape_path(curr_path: &OsStr) -> String {
+    let mut out_str = String::new();
+
+    for x in os_bytes(curr_path).utf8_chunks() {
+        for y in x.valid().chars() {
+            match y {
+                '\\' => out_str.push_str("\\\\"),
+                '\t' => out_str.push_str("\\t"),
+                '\n' => out_str.push_str("\\n"),
+                '\r' => out_str.push_str("\\r"),
+                _ => out_str.push(y)
             }
         }
+
+        for y in x.invalid() {
+            out_str.push_str(format!("\\x{:02X}", y).as_str());
+        }
+    }
+
+    out_str
+}
+
+// The unescape_path function undoes escape_path, giving back the path a report or message printed.
+// Anything that isn't one of our escapes is kept as it is, so reports from before we escaped paths
+// still read in the same as they always did (unless a name had a backslash in it).
+// Arguments are as follows:
+// curr_str: The printed path (or file name).
+pub fn unescape_path(curr_str: &str) -> OsString {
+    let in_bytes = curr_str.as_bytes();
+    let mut out_bytes = Vec::with_capacity(in_bytes.len());
+    let mut i = 0;
+
+    while i < in_bytes.len() {
+        let esc = if in_bytes[i] == b'\\' { in_bytes.get(i + 1) } else { None };
+
+        match esc {
+            Some(b'\\') => { out_bytes.push(b'\\'); i += 2; }
+            Some(b't') => { out_bytes.push(b'\t'); i += 2; }
+            Some(b'n') => { out_bytes.push(b'\n'); i += 2; }
+            Some(b'r') => { out_bytes.push(b'\r'); i += 2; }
+            Some(b'x') => match curr_str.get(i + 2..i + 4)
+                .filter(|x| x.bytes().all(|y| y.is_ascii_hexdigit())) {
+                Some(t) => { out_bytes.push(u8::from_str_radix(t, 16).unwrap()); i += 4; }
+                _ => { out_bytes.push(b'\\'); i += 1; }
+            },
+            _ => { out_bytes.push(in_bytes[i]); i += 1; }
+        }
+    }
+
+    os_from_bytes(out_bytes)
+}
+
+// The os_bytes function returns the raw bytes of curr_path. On unix that is exactly what the
+// filesystem gave us, elsewhere paths that aren't valid Unicode are converted lossily.
+#[cfg(unix)]
+pub fn os_bytes(curr_path: &OsStr) -> Vec<u8> {
+    curr_path.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn os_bytes(curr_path: &OsStr) -> Vec<u8> {
+    curr_path.to_string_lossy().as_bytes().to_vec()
+}
+
+// The os_from_bytes function is the reverse of os_bytes, turning raw path bytes back into an
+// OsString.
+#[cfg(unix)]
+pub fn os_from_bytes(path_bytes: Vec<u8>) -> OsString {
+    OsString::from_vec(path_bytes)
+}
+
+#[cfg(not(unix))]
+pub fn os_from_bytes(path_bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&path_bytes).to_string())
+}
+
+// The canon_roots function returns the search directories followed by the reference directories,
+// canonicalized the same way the file paths we collect are, so file paths can be compared against
+// them. Directories that can't be canonicalized are returned as given.
+pub fn canon_roots(curr_conf: &Config) -> Vec<PathBuf> {
+    curr_conf.search_path.iter().chain(curr_conf.ref_path.iter())
+        .map(|x| std::fs::canonicalize(x).unwrap_or_else(|_| x.clone()))
+        .collect()
+}
+
+// The relative_result function returns a copy of curr_fr with its paths made relative to the
+// longest of roots it is found under, which is what gets written to archives when the user asks
+// for relative paths. The root used is recorded too, as the same relative path can be a different
+// file under each root. Files not under any of the roots keep their absolute paths.
+// Arguments are as follows:
+// curr_fr: The file to make the paths relative for.
+// roots: The canonicalized search and reference directories (see canon_roots).
+pub fn relative_result(curr_fr: &FileResult, roots: &[PathBuf]) -> FileResult {
+    let mut rel_fr = curr_fr.clone();
+
+    let root = roots.iter().enumerate()
+        .filter(|x| curr_fr.file_path.starts_with(x.1))
+        .max_by_key(|x| x.1.as_os_str().len());
+
+    if let Some((i, t)) = root {
+
+        // starts_with already told us these prefixes are there
+        rel_fr.file_path = curr_fr.file_path.strip_prefix(t).unwrap().to_path_buf();
+        rel_fr.dir_path = match curr_fr.dir_path.strip_prefix(t) {
+            Ok(r) => r.to_path_buf(),
+            Err(_) => PathBuf::new()
+        };
+        rel_fr.root = Some(i);
+    }
+
+    rel_fr
+}
+
+// The prev_hash function looks for the file represented by curr_fr amongst the previously computed
+// hashes of the same size, returning the hash wrapped in a Some if we find a match, otherwise None.
+// A match on the full path (and mtime) is always taken. If the user asked for relaxed matching we
+// then try the inode or the file name (along with the mtime), which lets a directory that was moved
+// or renamed keep its hashes. A relaxed match is only trusted if every candidate agrees on the
+// hash, so two different files that happen to share a name, size and mtime don't get mixed up.
+// Arguments are as follows:
+// curr_fr: The file we need a hash for.
+// prev_ls: The previously computed hashes for files the same size as curr_fr.
+// curr_conf: The DuFF config, holding the matching mode the user wants.
+pub fn prev_hash(curr_fr: &FileResult, prev_ls: &[FileResult], curr_conf: &Config) -> Option<String> {
+    let same_time: Vec<&FileResult> = prev_ls.iter()
+        .filter(|x| x.mtime == curr_fr.mtime)
+        .collect();
+
+    if let Some(t) = same_time.iter().find(|x| x.file_path == curr_fr.file_path) {
+        return Some(t.hash.clone());
+    }
+
+    let candidates: Vec<&&FileResult> = match curr_conf.hash_match.as_str() {
+        "inode" if curr_fr.inode != 0 => same_time.iter()
+            .filter(|x| (x.inode == curr_fr.inode) && (x.dev == curr_fr.dev))
+            .collect(),
+        "name" => same_time.iter().filter(|x| x.file_name == curr_fr.file_name).collect(),
+        _ => return None
+    };
+
+    match candidates.first() {
+        Some(t) if candidates.iter().all(|x| x.hash == t.hash) => Some(t.hash.clone()),
+        _ => None
+    }
+}
+
+// The is_ref function checks whether the file represented by curr_fr lives inside one of the
+// user's reference directories, returning a bool indicating the result. Both the file path and the
+// reference directories are canonicalized, so a simple prefix check on the path components works.
+// Arguments are as follows:
+// curr_fr: The FileResult to check
+// curr_conf: The current configuration, holding the reference directories.
+pub fn is_ref(curr_fr: &FileResult, curr_conf: &Config) -> bool {
+    curr_conf.ref_path.iter().any(|x| curr_fr.file_path.starts_with(x))
+}
+
+// The keep_group function decides whether a group of potential duplicates (grouped by size or by
+// size and hash) is worth keeping around, returning a bool indicating the result. Normally that is
+// just any group with at least 2 files, but when comparing against reference directories we need
+// at least one source and one reference file, unless the user also wants source only duplicates.
+// Arguments are as follows:
+// curr_group: The group of FileResult structs to check
+// curr_conf: The current configuration.
+pub fn keep_group(curr_group: &[FileResult], curr_conf: &Config) -> bool {
+    if !curr_conf.have_ref {
+        return curr_group.len() > 1;
+    }
+
+    let n_ref = curr_group.iter().filter(|x| is_ref(x, curr_conf)).count();
+    let n_src = curr_group.len() - n_ref;
+
+    ((n_src >= 1) && (n_ref >= 1)) || (curr_conf.src_dupes && (n_src > 1))
+}
+
+// The unique_dict function takes the files found to have no copies anywhere else and turns them
+// into the same hashmap structure write_report expects, with each file as its own group. If the
+// user only wants unique files from under a particular directory everything else is dropped here.
+// A file only looks unique after an interrupt because its copies were never found or hashed, so
+// nothing is listed at all then (write_report says why).
+// Arguments are as follows:
+// uniq_ls: The unique files found.
+// curr_conf: The current configuration.
+pub fn unique_dict(uniq_ls: Vec<FileResult>,
+                   curr_conf: &Config) -> HashMap<String, Vec<FileResult>> {
+    let mut dict = HashMap::new();
+
+    if interrupt::interrupted() {
+        return dict;
+    }
+
+    for x in uniq_ls {
+        let under_root = x.file_path.starts_with(&curr_conf.unique_root);
+
+        if curr_conf.have_unique_root && !under_root {
+            continue
+        }
+
+        dict.insert(escape_path(x.file_path.as_os_str()), vec![x]);
+    }
+
+    dict
+}
+
+// The target_dict function pulls out every location each of the user's target files can be found
+// at, taking the hashmap of files grouped by size and hash and returning a new hashmap keyed by the
+// target file's path. Targets with no copies found are left out.
+// Arguments are as follows:
+// targets: The user's target files, already hashed.
+// dict: The files found during the search, grouped by size and hash ("size_hash" keys).
+pub fn target_dict(targets: &[FileResult],
+                   dict: &HashMap<String, Vec<FileResult>>) -> HashMap<String, Vec<FileResult>> {
+    let mut t_dict = HashMap::new();
+
+    for x in targets.iter() {
+        let key = format!("{}_{}", x.size, x.hash);
+
+        // A target inside a search directory gets found along with everything else, but it isn't
+        // a copy of itself.
+        let copies: Vec<FileResult> = dict.get(&key).into_iter().flatten()
+            .filter(|y| y.file_path != x.file_path)
+            .cloned()
+            .collect();
+
+        if !copies.is_empty() {
+            t_dict.insert(escape_path(x.file_path.as_os_str()), copies);
+        }
+    }
+
+    t_dict
+}
+
+// The missing_targets function lets the user know about each of their target files that no copies
+// were found for, unless they asked for silence.
+// Arguments are as follows:
+// targets: The user's target files.
+// t_dict: The copies found for each target, see target_dict.
+// curr_conf: The DuFF config.
+pub fn missing_targets(targets: &[FileResult], t_dict: &HashMap<String, Vec<FileResult>>,
+                       curr_conf: &Config) {
+    for x in targets.iter() {
+        let target_str = escape_path(x.file_path.as_os_str());
+
+        if !t_dict.contains_key(&target_str) && !curr_conf.silent {
+            println!("No copies found for target file {}", target_str);
+        }
     }
 }
 
+// The clean_up function finishes off the log and archive files, waiting for everything to be
+// written out and moving them into place, returning false if either couldn't be written.
+// Arguments are as follows:
+// log_writer: The writer for the log file.
+// arch_writer: The writer for the archive file.
+pub fn clean_up(log_writer: &mut RecordWriter, arch_writer: &mut RecordWriter) -> bool {
+    let log_ok = log_writer.finish();
+    let arch_ok = arch_writer.finish();
+
+    log_ok && arch_ok
+}
+
 // This function writes a report file out to the file represented by rep_file. It iterates through
-// all of the duplicate files in the input dict making entries for each one.
-pub fn write_report<T>(mut rep_file: File, dict: HashMap<T, Vec<FileResult>>) {
+// all of the duplicate files in the input dict making entries for each one. When comparing against
+// reference directories an extra column tells the user which set each file belongs to, when
+// looking for copies of target files an extra column holds the target (the dict key) each file is a
+// copy of, and when detecting file types a column holds each file's type. The last column holds
+// each group's id (see group_id), which stays the same from run to run. Unless the user asked for
+// the report unsorted, groups are written largest files first (ties broken by their ids), so two
+// runs over the same files give the same report whatever order the files were found in.
+pub fn write_report<T: Display>(mut rep_file: File, dict: HashMap<T, Vec<FileResult>>,
+                                curr_conf: &Config) {
 
     // TODO: Replace unwrap
+    // If we were interrupted, make sure nobody mistakes this report for the full story. Unique
+    // files can't be told apart from ones whose copies we never got to, so none are listed.
+    if interrupt::interrupted() && curr_conf.unique {
+        writeln!(rep_file, "# INCOMPLETE: DuFF was interrupted before it could tell which files \
+                            have no copies, so no files are listed in this report.").unwrap();
+    } else if interrupt::interrupted() {
+        writeln!(rep_file, "# INCOMPLETE: DuFF was interrupted, files it had not hashed yet are \
+                            missing from this report.").unwrap();
+    }
+
     // Write the simple header
-    writeln!(rep_file, "File Count\tDuplicate Number\tName\tPath\tFile Size\tModified Time").unwrap();
+    let mut header_str = String::from("File Count\tDuplicate Number\tName\tPath\tFile Size\t\
+                                       Modified Time");
+
+    if curr_conf.have_ref {
+        header_str.push_str("\tSet");
+    } else if curr_conf.have_target {
+        header_str.push_str("\tTarget");
+    }
 
+    if curr_conf.detect_types {
+        header_str.push_str("\tType");
+    }
 
-    // file_cnt tracks the number of unique files (files that have multiple copies)
-    let mut file_cnt = 1;
+    header_str.push_str("\tGroup ID");
 
-    // Go through th entire dictionary
-    for (_, v) in dict.iter() {
+    writeln!(rep_file, "{}", header_str).unwrap();
+
+    // Work out each group's id up front, so we can sort on it.
+    let mut groups: Vec<(String, T, Vec<FileResult>)> = dict.into_iter()
+        .map(|(k, v)| (group_id(&k.to_string(), &v, curr_conf.have_target), k, v))
+        .collect();
+
+    if curr_conf.sort_output {
+        groups.sort_by(|a, b| {
+            let a_size = a.2.first().map_or(0, |x| x.size);
+            let b_size = b.2.first().map_or(0, |x| x.size);
+
+            b_size.cmp(&a_size).then_with(|| a.0.cmp(&b.0))
+        });
+    }
+
+    // Go through th entire dictionary, file_cnt tracks the number of unique files (files that have
+    // multiple copies)
+    for (file_cnt, (id, k, mut v)) in (1..).zip(groups) {
 
         // Create string we will build on
         let mut out_str = String::new();
 
+        // Keep the duplicates in path order so the same files always get the same duplicate
+        // number, no matter what order the directory traversal happened to find them in.
+        v.sort_by(|a, b| a.file_path.cmp(&b.file_path));
 
-        // dupe_cnt represents the number duplicate of current file_cnt this file is
-        let mut dupe_cnt = 1;
-
-        // Go through each vector of duplicate files
-        for y in v.iter() {
+        // Go through each vector of duplicate files, dupe_cnt represents the number duplicate of
+        // current file_cnt this file is
+        for (dupe_cnt, y) in (1..).zip(v.iter()) {
 
             // Append information for current duplicate to our string for output
-            out_str.push_str(format!("{}\t{}\t{}\t{}\t{}\t{}\n", file_cnt, dupe_cnt,
-                                     y.file_name, y.dir_path, y.size, y.mtime).as_str());
+            out_str.push_str(format!("{}\t{}\t{}\t{}\t{}\t{}", file_cnt, dupe_cnt,
+                                     escape_path(&y.file_name), escape_path(y.dir_path.as_os_str()),
+                                     y.size, y.mtime).as_str());
+
+            if curr_conf.have_ref {
+                if is_ref(y, curr_conf) {
+                    out_str.push_str("\treference");
+                } else {
+                    out_str.push_str("\tsource");
+                }
+            }
 
+            if curr_conf.have_target {
+                out_str.push_str(format!("\t{}", k).as_str());
+            }
+
+            if curr_conf.detect_types {
+                out_str.push_str(format!("\t{}", y.file_type).as_str());
+            }
 
-            dupe_cnt = dupe_cnt + 1;
+            out_str.push_str(format!("\t{}\n", id).as_str());
         }
 
         // Write out the report entry for this unique file.
         write!(rep_file, "{}", out_str).unwrap();
+    }
+}
 
-
-        file_cnt = file_cnt + 1;
+// The group_id function gives a group of files in the report an id that doesn't depend on the
+// order anything was found or written in. Groups of hashed files take the first 16 hex digits of
+// their hash, so the same content always gets the same id. Anything else (unhashed unique files,
+// the empty file and directory groups) gets a hash of its dict key instead. When looking for copies
+// of target files two targets can have the same content, so each target's group gets a hash of
+// the target's path tacked on to its hash id (e.g. 412c8d88327ff840-1a2b3c4d) to keep them apart.
+// Arguments are as follows:
+// key: The group's key in the report dict.
+// members: The files in the group.
+// per_key: Whether groups with the same content need telling apart by their keys (targets).
+pub fn group_id(key: &str, members: &[FileResult], per_key: bool) -> String {
+    let key_hash = format!("{:016x}", xxh3::hash64(key.as_bytes()));
+
+    match members.first().and_then(|x| hash_id(&x.hash)) {
+        Some(t) if per_key => format!("{}-{}", t, &key_hash[..8]),
+        Some(t) => t,
+        None => key_hash
     }
 }
 
+// The hash_id function returns the id a group of files with this hash gets (see group_id), None if
+// there isn't a hash.
+// Arguments are as follows:
+// hash: The files' hash.
+pub fn hash_id(hash: &str) -> Option<String> {
+    hash.parse::<u128>().ok().map(|x| format!("{:032x}", x)[..16].to_string())
+}
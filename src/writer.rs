@@ -0,0 +1,182 @@
+// This file/module holds the RecordWriter, which takes care of writing the log and archive files.
+// Lines are handed to a dedicated writer thread over a channel, so the threads doing the real work
+// never touch the file themselves and can't interleave (and tear) each other's lines. The writer
+// thread buffers its writes, checkpoints them to disk (flush + fsync) every so often, and only
+// renames the file to its final name once everything has been written, so a file with the final
+// name is always complete. If a write fails we let the user know once and stop writing to that
+// file, rather than panicking part way through a run.
+
+// To create the file we write to, using the same helper as the rest of DuFF.
+use crate::util;
+
+// For the file itself, and buffering our writes to it.
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+// For the writer thread and how often it checkpoints.
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// The channel between the workers and the writer thread.
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+
+// How many lines we write before checkpointing them to disk.
+const CHECKPOINT_LINES: u64 = 10000;
+
+// The longest we go between checkpoints while lines are still coming in.
+const CHECKPOINT_SECS: u64 = 5;
+
+// The RecordWriter struct holds the sending side of the channel to the writer thread, along with
+// the thread itself so we can wait for it to finish up.
+pub struct RecordWriter {
+
+    // Whether we are writing anything at all, if not every function here is a no-op.
+    enabled : bool,
+
+    // The path the finished file ends up at, it is written to file_str.part until then.
+    file_str : String,
+
+    // The sending side of the channel to the writer thread, dropped to tell it we are done.
+    tx : Option<Sender<String>>,
+
+    // The writer thread, which hands back the number of lines written or the error that stopped it.
+    handle : Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl RecordWriter {
+
+    // The start function creates the file (file_str with .part tacked on) and starts up the writer
+    // thread for it, if enabled. Creating the file goes through util::open_file, so an output
+    // directory we can't write to is caught right at the start of the run.
+    // Arguments are as follows:
+    // file_str: Full path of the finished file.
+    // enabled: Whether the user actually wants this file.
+    // out_dir: The output directory, only used in error messages.
+    // user_dir: Whether the user specified the output directory, only used in error messages.
+    pub fn start(file_str: &str, enabled: bool, out_dir: &String, user_dir: bool) -> RecordWriter {
+        let mut writer = RecordWriter {
+            enabled : enabled,
+            file_str : file_str.to_string(),
+            tx : None,
+            handle : None,
+        };
+
+        if !enabled {
+            return writer;
+        }
+
+        let out_file = util::open_file(&part_path(file_str), out_dir, user_dir);
+        let (tx, rx) = crossbeam_channel::unbounded::<String>();
+
+        writer.tx = Some(tx);
+        writer.handle = Some(thread::spawn(move || write_lines(out_file, rx)));
+
+        writer
+    }
+
+    // The write function hands a single line (without its newline) to the writer thread.
+    pub fn write(&self, line: String) {
+        if let Some(tx) = &self.tx {
+
+            // The writer thread keeps receiving until we drop tx, even after a failed write, so
+            // this can't fail.
+            tx.send(line).unwrap();
+        }
+    }
+
+    // The finish function tells the writer thread there is nothing more coming, waits for it to
+    // write everything out and then renames the file to its final name. If anything went wrong the
+    // user is told, the partial file is left where it is, and we return false.
+    pub fn finish(&mut self) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        // Dropping the sender closes the channel, which is the writer thread's cue to finish up.
+        drop(self.tx.take());
+
+        let write_res = match self.handle.take() {
+            Some(t) => match t.join() {
+                Ok(r) => r,
+                Err(_) => Err(io::Error::other("writer thread panicked"))
+            },
+
+            // Already finished
+            None => return true
+        };
+
+        match write_res.and_then(|_| fs::rename(part_path(&self.file_str), &self.file_str)) {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Error writing {}, it is incomplete and has been left at {}.\nError text: \
+                          {}", self.file_str, part_path(&self.file_str), e);
+                false
+            }
+        }
+    }
+}
+
+// The write_lines function is what the writer thread runs. It writes every line that comes down rx
+// out to out_file, checkpointing every CHECKPOINT_LINES lines or CHECKPOINT_SECS seconds, until the
+// channel is closed. After a failed write we keep draining rx so nobody is left waiting on us, but
+// don't try to write anything else.
+// Arguments are as follows:
+// out_file: The (.part) file to write to.
+// rx: The receiving side of the channel lines come down.
+fn write_lines(out_file: File, rx: Receiver<String>) -> io::Result<u64> {
+    let mut out_writer = BufWriter::new(out_file);
+    let mut n_lines = 0;
+    let mut n_since = 0;
+    let mut last_checkpoint = Instant::now();
+    let mut write_err: Option<io::Error> = None;
+
+    loop {
+        let line = match rx.recv_timeout(Duration::from_secs(CHECKPOINT_SECS)) {
+            Ok(t) => Some(t),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+
+        if write_err.is_some() {
+            continue
+        }
+
+        if let Some(t) = line {
+            if let Err(e) = writeln!(out_writer, "{}", t) {
+                write_err = Some(e);
+                continue
+            }
+
+            n_lines += 1;
+            n_since += 1;
+        }
+
+        if (n_since > 0) && ((n_since >= CHECKPOINT_LINES) ||
+            (last_checkpoint.elapsed().as_secs() >= CHECKPOINT_SECS)) {
+            if let Err(e) = checkpoint(&mut out_writer) {
+                write_err = Some(e);
+                continue
+            }
+
+            n_since = 0;
+            last_checkpoint = Instant::now();
+        }
+    }
+
+    match write_err {
+        Some(e) => Err(e),
+        None => checkpoint(&mut out_writer).map(|_| n_lines)
+    }
+}
+
+// The checkpoint function pushes everything written so far out of our buffer and onto the disk.
+fn checkpoint(out_writer: &mut BufWriter<File>) -> io::Result<()> {
+    out_writer.flush()?;
+    out_writer.get_ref().sync_data()
+}
+
+// The part_path function gives the path a file is written to until it is finished.
+fn part_path(file_str: &str) -> String {
+    format!("{}.part", file_str)
+}
@@ -1,3 +1,7 @@
+// The older tests spell out their clean up and checks in their own way, clippy would rather they
+// didn't but they're left as they were written.
+#![allow(clippy::unnecessary_to_owned, clippy::bool_assert_comparison)]
+
 mod common;
 
 use assert_cmd::prelude::*;
@@ -27,7 +31,7 @@ fn search_dir_dne() -> Result<(), Box<dyn std::error::Error>> {
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     cmd.arg("-d")
       .arg(in_str)
@@ -93,7 +97,7 @@ fn still_match_upper_lim_filtered() -> Result<(), Box<dyn std::error::Error>> {
     let result_row_1: String = format!("1\t1\t{}", good_file_1);
     let result_row_2: String = format!("1\t2\t{}", good_file_2);
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -201,7 +205,7 @@ fn no_dupe_upper_lim_filtered() -> Result<(), Box<dyn std::error::Error>> {
     let result_row_2: String = format!("1\t2\t{}", good_file_2);
 
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -308,7 +312,7 @@ fn no_match_extension_filtered() -> Result<(), Box<dyn std::error::Error>> {
     let result_row_1: String = format!("1\t1\t{}", good_file_1);
     let result_row_2: String = format!("1\t2\t{}", good_file_2);
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -415,7 +419,7 @@ fn still_match_diff_extension() -> Result<(), Box<dyn std::error::Error>> {
     let result_row_1: String = format!("1\t1\t{}", good_file_1);
     let result_row_2: String = format!("1\t2\t{}", good_file_2);
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -510,7 +514,7 @@ fn still_match_despite_extension() -> Result<(), Box<dyn std::error::Error>> {
     static RESULT_ROW_1: &str = "1\t1\tgood_in_1";
     static RESULT_ROW_2: &str = "1\t2\tgood_in_2";
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -600,7 +604,7 @@ fn perfect_match() -> Result<(), Box<dyn std::error::Error>> {
     static RESULT_ROW_1: &str = "1\t1\tperf_match_good_in_1.txt";
     static RESULT_ROW_2: &str = "1\t2\tperf_match_good_in_2.txt";
 
-    let mut cmd = Command::cargo_bin("duff")?;
+    let mut cmd = common::duff();
 
     let in_str = home_dir.display().to_string().to_owned();
     let out_str = home_dir.display().to_string().to_owned();
@@ -651,4 +655,2256 @@ fn perfect_match() -> Result<(), Box<dyn std::error::Error>> {
     // Main test, should only succeed if final_bool is true
     assert!(final_bool);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+// Comparing a source directory against a reference directory. The good files are copies of one
+// another with one living in each directory, the bad files are copies of one another that only
+// live in the source directory, so they should only be reported when asking for source dupes.
+fn compare_against_reference() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("compare_against_reference");
+    let src_dir = home_dir.join("src");
+    let ref_dir = home_dir.join("ref");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&src_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&ref_dir, "good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&src_dir, "bad_in_1.txt", common::BAD_DATA);
+    common::write_file(&src_dir, "bad_in_2.txt", common::BAD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&src_dir)
+        .arg("-R")
+        .arg(&ref_dir)
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+    fs::remove_dir_all(&out_dir)?;
+
+    let good_only = file_content.lines().count() == 3 &&
+        file_content.contains("good_in_1.txt") && file_content.contains("good_in_2.txt") &&
+        file_content.contains("\tsource") && file_content.contains("\treference") &&
+        !file_content.contains("bad_in_");
+
+    // Now ask for the source only duplicates as well
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&src_dir)
+        .arg("-R")
+        .arg(&ref_dir)
+        .arg("--src_dupes")
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+    let with_src = file_content.lines().count() == 5 &&
+        file_content.contains("bad_in_1.txt") && file_content.contains("bad_in_2.txt");
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert!(good_only);
+    assert!(with_src);
+    Ok(())
+}
+
+
+#[test]
+// A reference directory inside the search directory is found by both, the files in it should still
+// only be reported once, not as copies of themselves.
+fn nested_reference_dir() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("nested_reference_dir");
+    let src_dir = home_dir.join("src");
+    let ref_dir = src_dir.join("ref");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&src_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&ref_dir, "good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&ref_dir, "bad_in_1.txt", common::BAD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&src_dir)
+        .arg("-R")
+        .arg(&ref_dir)
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(file_content.lines().count(), 3);
+    assert_eq!(file_content.matches("good_in_1.txt").count(), 1);
+    assert_eq!(file_content.matches("good_in_2.txt").count(), 1);
+    assert!(!file_content.contains("bad_in_1.txt"));
+    Ok(())
+}
+
+
+#[test]
+// Looking for unique files, restricted to the old directory. good_in_2 has a copy in the keep
+// directory and bad_in_1 is unique but lives outside of the old directory, so only same_size (which
+// matches the good files in size but not in content) should be reported.
+fn unique_under_root() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("unique_under_root");
+    let keep_dir = home_dir.join("keep");
+    let old_dir = home_dir.join("old");
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    common::write_file(&keep_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&keep_dir, "bad_in_1.txt", common::BAD_DATA);
+    common::write_file(&old_dir, "good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&old_dir, "same_size.txt", &same_size_data);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(format!("{},{}", keep_dir.display(), old_dir.display()))
+        .arg("-U")
+        .arg("--unique_under")
+        .arg(&old_dir)
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(file_content.lines().count(), 2);
+    assert!(file_content.contains("1\t1\tsame_size.txt"));
+    Ok(())
+}
+
+
+#[test]
+// Looking for copies of a single target file that lives outside of the search directory. Both good
+// files are copies of the target and should be reported, the bad file should not, even though it
+// has the same size as the target.
+fn find_target_copies() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("find_target_copies");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    let target = common::write_file(&home_dir, "target.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "nested/good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "bad_in_1.txt", &same_size_data);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-t")
+        .arg(&target)
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(file_content.lines().count(), 3);
+    assert!(file_content.contains("1\t1\tgood_in_1.txt"));
+    assert!(file_content.contains("1\t2\tgood_in_2.txt"));
+    assert!(file_content.contains("target.txt"));
+    assert!(!file_content.contains("bad_in_1.txt"));
+    Ok(())
+}
+
+
+#[test]
+// Two targets with the same content, both inside the search directory. Each should be reported with
+// the other (and the one other copy) but not itself, in groups with their own ids.
+fn find_target_copies_in_search_dir() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("find_target_copies_in_search_dir");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    let target_1 = common::write_file(&search_dir, "target_1.txt", common::GOOD_DATA);
+    let target_2 = common::write_file(&search_dir, "target_2.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("-t")
+        .arg(&target_1)
+        .arg("-t")
+        .arg(&target_2)
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let file_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    let rows: Vec<Vec<&str>> = file_content.lines().skip(1)
+        .map(|x| x.split('\t').collect())
+        .collect();
+
+    // Name, Target and Group ID columns.
+    let self_copies = rows.iter().filter(|x| x[6].ends_with(x[2])).count();
+    let mut ids: Vec<&str> = rows.iter().map(|x| x[7]).collect();
+    ids.dedup();
+
+    assert_eq!(rows.len(), 4);
+    assert_eq!(self_copies, 0);
+    assert_eq!(ids.len(), 2);
+    Ok(())
+}
+
+
+// A target file and a --unique_under directory whose names aren't valid UTF-8 should still match the
+// files found in the search. A target no other file is the size of should be reported as having no
+// copies, even though DuFF stops before hashing anything.
+#[cfg(unix)]
+#[test]
+fn non_utf8_target_and_unique_root() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let home_dir = common::test_dir("non_utf8_target_and_unique_root");
+    let search_dir = home_dir.join("search");
+    let uniq_dir = search_dir.join(OsStr::from_bytes(b"uniq_\xff"));
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    let target = home_dir.join(OsStr::from_bytes(b"target_\xff.txt"));
+    fs::write(&target, common::GOOD_DATA)?;
+    let lone_target = common::write_file(&home_dir, "lone_target.txt", "Nothing else is this size");
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&uniq_dir, "same_size.txt", &same_size_data);
+
+    let mut reports = Vec::new();
+    let mut lone_out = String::new();
+
+    for x in ["target", "unique", "lone"].iter() {
+        let run_dir = out_dir.join(x);
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        match *x {
+            "target" => cmd.arg("-t").arg(&target),
+            "unique" => cmd.arg("-U").arg("--unique_under").arg(&uniq_dir),
+            _ => cmd.arg("-t").arg(&lone_target)
+        };
+
+        let run_out = cmd.output()?;
+        assert!(run_out.status.success());
+
+        if *x == "lone" {
+            lone_out = String::from_utf8(run_out.stdout)?;
+        } else {
+            reports.push(common::read_report(&run_dir));
+        }
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(reports[0].lines().count(), 2);
+    assert!(reports[0].contains("good_in_1.txt"));
+    assert_eq!(reports[1].lines().count(), 2);
+    assert!(reports[1].contains("same_size.txt"));
+    assert!(lone_out.contains("No copies found for target file"));
+    assert!(lone_out.contains("lone_target.txt"));
+    Ok(())
+}
+
+
+#[test]
+// Feeding DuFF the files directly instead of a directory, first as a newline separated list file
+// and then as a NUL separated list on stdin. The bad file is left out of the list, so the good
+// files should be reported and nothing else, even though the bad file is a copy of them.
+fn files_from_list() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("files_from_list");
+    let out_dir = home_dir.join("out");
+
+    let good_1 = common::write_file(&home_dir, "files/good_in_1.txt", common::GOOD_DATA);
+    let good_2 = common::write_file(&home_dir, "files/good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&home_dir, "files/bad_in_1.txt", common::GOOD_DATA);
+
+    let list_data = format!("{}\n{}\n", good_1.display(), good_2.display());
+    let list_file = common::write_file(&home_dir, "list.txt", &list_data);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("--files-from")
+        .arg(&list_file)
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let list_content = common::read_report(&out_dir);
+    fs::remove_dir_all(&out_dir)?;
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("--files-from")
+        .arg("-")
+        .arg("-o")
+        .arg(&out_dir)
+        .with_stdin()
+        .buffer(format!("{}\0{}\0", good_1.display(), good_2.display()))
+        .assert()
+        .success();
+
+    let stdin_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for file_content in [list_content, stdin_content].iter() {
+        assert_eq!(file_content.lines().count(), 3);
+        assert!(file_content.contains("1\t1\tgood_in_1.txt"));
+        assert!(file_content.contains("1\t2\tgood_in_2.txt"));
+    }
+    Ok(())
+}
+
+
+#[test]
+// Making sure the hash cache is both written and consulted. The two files are the same size but
+// have different content, so the first run finds no duplicates. We then doctor the cache so both
+// files have the same hash, and as neither file changed the second run should trust the cache and
+// report them as duplicates.
+fn hash_cache_reused() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("hash_cache_reused");
+    let search_dir = home_dir.join("search");
+    let cache_dir = home_dir.join("cache");
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", &same_size_data);
+
+    // Report files are named down to the second, so give each run its own output directory
+    let mut found = Vec::new();
+
+    for x in 0..2 {
+        let run_dir = out_dir.join(x.to_string());
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--cache_dir")
+            .arg(&cache_dir)
+            .arg("-o")
+            .arg(&run_dir);
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&run_dir).lines().count());
+
+        // Swap every cached hash out for the same made up one
+        common::fake_hashes(&cache_dir.join("hash_cache.jsonl"));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    // Only the second run should have found anything
+    assert_eq!(found, vec![1, 3]);
+    Ok(())
+}
+
+
+#[test]
+// Previously computed hashes: the archived hashes of two different files of the same size are
+// swapped for the same made up one and the directory is renamed. Matching by path should hash both
+// files again and find nothing, matching by name should reuse the made up hashes and find a group.
+// Hashes found by matching names are only guesses, so they mustn't end up in the hash cache, and a
+// last run with just the cache should find nothing.
+fn hash_file_moved_dir() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("hash_file_moved_dir");
+    let search_dir = home_dir.join("search");
+    let moved_dir = home_dir.join("moved");
+    let cache_dir = home_dir.join("cache");
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", &same_size_data);
+
+    let first_dir = out_dir.join("first");
+    fs::create_dir_all(&first_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-a")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&first_dir);
+
+    cmd.assert()
+        .success();
+
+    // Swap every archived hash out for the same made up one
+    let arch_file = glob(&format!("{}/*.arch", first_dir.display()))?.next().unwrap()?;
+    common::fake_hashes(&arch_file);
+    fs::rename(&search_dir, &moved_dir)?;
+
+    let mut found = Vec::new();
+
+    for x in ["path", "name"].iter() {
+        let run_dir = out_dir.join(x);
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&moved_dir)
+            .arg("-x")
+            .arg(&arch_file)
+            .arg("--hash_match")
+            .arg(x)
+            .arg("--cache_dir")
+            .arg(&cache_dir)
+            .arg("-o")
+            .arg(&run_dir);
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&run_dir).lines().count());
+    }
+
+    let cache_run_dir = out_dir.join("cache_only");
+    fs::create_dir_all(&cache_run_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&moved_dir)
+        .arg("--cache_dir")
+        .arg(&cache_dir)
+        .arg("-o")
+        .arg(&cache_run_dir)
+        .assert()
+        .success();
+
+    found.push(common::read_report(&cache_run_dir).lines().count());
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(found, vec![1, 3, 1]);
+    Ok(())
+}
+
+
+#[test]
+// A relative archive only knows x.txt by its path under the root it was found in, here the first
+// search directory. The second search directory has its own x.txt, the same size and mtime but with
+// different content, which mustn't be handed the first one's hash (making it a false duplicate).
+fn relative_archive_two_roots() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("relative_archive_two_roots");
+    let dir_a = home_dir.join("a");
+    let dir_b = home_dir.join("b");
+    let arch_dir = home_dir.join("out_arch");
+    let run_dir = home_dir.join("out_run");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    let a_file = common::write_file(&dir_a, "x.txt", common::GOOD_DATA);
+    common::write_file(&dir_a, "x_copy.txt", common::GOOD_DATA);
+    let b_file = common::write_file(&dir_b, "x.txt", &same_size_data);
+    File::options().write(true).open(&b_file)?.set_modified(fs::metadata(&a_file)?.modified()?)?;
+    fs::create_dir_all(&arch_dir)?;
+    fs::create_dir_all(&run_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&dir_a)
+        .arg("-a")
+        .arg("--relative-paths")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&arch_dir)
+        .assert()
+        .success();
+
+    let arch_file = glob(&format!("{}/*.arch", arch_dir.display()))?.next().unwrap()?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&dir_a)
+        .arg(&dir_b)
+        .arg("-x")
+        .arg(&arch_file)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&run_dir)
+        .assert()
+        .success();
+
+    let rep_content = common::read_report(&run_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(rep_content.lines().count(), 3);
+    assert!(!rep_content.contains("/b\t"));
+    Ok(())
+}
+
+
+#[test]
+// A relative archive written searching a then b, reused searching b then a. Each x.txt has to get
+// its own hash back: a/y.txt is a copy of b/x.txt, and all three files share a size and mtime, so
+// handing a/x.txt the hash archived for b/x.txt would make it a false duplicate of a/y.txt.
+fn relative_archive_reordered_roots() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("relative_archive_reordered_roots");
+    let dir_a = home_dir.join("a");
+    let dir_b = home_dir.join("b");
+    let arch_dir = home_dir.join("out_arch");
+    let run_dir = home_dir.join("out_run");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    let a_x = common::write_file(&dir_a, "x.txt", common::GOOD_DATA);
+    let a_y = common::write_file(&dir_a, "y.txt", &same_size_data);
+    let b_x = common::write_file(&dir_b, "x.txt", &same_size_data);
+
+    let mtime = fs::metadata(&a_x)?.modified()?;
+    for x in [&a_y, &b_x].iter() {
+        File::options().write(true).open(x)?.set_modified(mtime)?;
+    }
+    fs::create_dir_all(&arch_dir)?;
+    fs::create_dir_all(&run_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&dir_a)
+        .arg("-d")
+        .arg(&dir_b)
+        .arg("-a")
+        .arg("--relative-paths")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&arch_dir)
+        .assert()
+        .success();
+
+    let arch_file = glob(&format!("{}/*.arch", arch_dir.display()))?.next().unwrap()?;
+
+    // Only the archive can tell the run what the files hold.
+    common::fake_hashes(&arch_file);
+    let mut arch_content = String::new();
+
+    for curr_line in fs::read_to_string(&arch_file)?.lines() {
+        // b/x.txt (the second root) and a/y.txt hold the same content.
+        if (curr_line.contains("\"x.txt\"") && curr_line.contains("\"root\":1")) ||
+            curr_line.contains("\"y.txt\"") {
+            arch_content.push_str(&format!("{}\n", curr_line.replace("\"hash\":\"42\"",
+                                                                     "\"hash\":\"43\"")));
+        } else {
+            arch_content.push_str(&format!("{}\n", curr_line));
+        }
+    }
+    fs::write(&arch_file, arch_content)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&dir_b)
+        .arg("-d")
+        .arg(&dir_a)
+        .arg("-x")
+        .arg(&arch_file)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&run_dir)
+        .assert()
+        .success();
+
+    let rep_content = common::read_report(&run_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    let rows: Vec<&str> = rep_content.lines().skip(1).collect();
+
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().any(|x| x.contains("y.txt")));
+    assert!(rows.iter().any(|x| x.contains("x.txt") && x.contains("/b\t")));
+    Ok(())
+}
+
+
+#[test]
+// Archives from elsewhere: like hash_file_moved_dir, but the directory move is handled by mapping
+// the old path onto the new one, for an archive with absolute paths and one with relative paths.
+// Both should reuse the made up hashes and find a group. Without the map the relative archive's
+// root isn't one of the search directories, so its hashes shouldn't be used.
+fn path_map_and_relative_archive() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("path_map_and_relative_archive");
+    let out_dir = home_dir.join("out");
+
+    let same_size_data = common::GOOD_DATA.replace("Same hash", "Diff hash");
+
+    let mut found = Vec::new();
+
+    for x in ["map", "relative", "unmapped"].iter() {
+        let search_dir = home_dir.join(format!("{}_search", x));
+        let moved_dir = home_dir.join(format!("{}_moved", x));
+        let arch_dir = out_dir.join(format!("{}_arch", x));
+        let run_dir = out_dir.join(x);
+
+        common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+        common::write_file(&search_dir, "good_in_2.txt", &same_size_data);
+        fs::create_dir_all(&arch_dir)?;
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("-a")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&arch_dir);
+
+        if *x != "map" {
+            cmd.arg("--relative-paths");
+        }
+
+        cmd.assert()
+            .success();
+
+        let arch_file = glob(&format!("{}/*.arch", arch_dir.display()))?.next().unwrap()?;
+        common::fake_hashes(&arch_file);
+
+        let old_path = fs::canonicalize(&search_dir)?;
+        fs::rename(&search_dir, &moved_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&moved_dir)
+            .arg("-x")
+            .arg(&arch_file)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        if *x != "unmapped" {
+            cmd.arg("--path-map")
+                .arg(format!("{}={}", old_path.display(), fs::canonicalize(&moved_dir)?.display()));
+        }
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&run_dir).lines().count());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(found, vec![3, 3, 1]);
+    Ok(())
+}
+
+
+#[test]
+// Log and archive writing: both are written out in full and moved into place by the end of the run,
+// leaving nothing else (partial or hidden files) behind in the output directory.
+fn log_and_archive_finished() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("log_and_archive_finished");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-a")
+        .arg("-g")
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let mut out_exts: Vec<String> = fs::read_dir(&out_dir)?
+        .map(|x| x.unwrap().path().extension().unwrap().to_string_lossy().to_string())
+        .collect();
+    out_exts.sort();
+
+    let log_file = glob(&format!("{}/*.log", out_dir.display()))?.next().unwrap()?;
+    let log_content = fs::read_to_string(log_file)?;
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(out_exts, vec!["arch", "log", "report"]);
+    assert!(log_content.starts_with("{\"duff_header\":"));
+    assert_eq!(log_content.lines().filter(|x| x.contains("good_in_")).count(), 4);
+    assert!(log_content.contains("# Starting hashing"));
+    Ok(())
+}
+
+
+#[test]
+// Config files: the search directory comes from a config file and the profile sets an upper size
+// limit below the size of the good files, so nothing should be found, until the command line
+// overrides that limit with one above it.
+fn config_file_profile() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("config_file_profile");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+
+    let conf_file = common::write_file(&home_dir, "duff.toml",
+                                       &format!("dir = [\"{}\"]\n\n[profiles.small]\nuplim = \"70B\"\n",
+                                                search_dir.display()));
+
+    let mut found = Vec::new();
+    let mut overviews = Vec::new();
+
+    for x in [None, Some("90B")].iter() {
+        let run_dir = out_dir.join(x.unwrap_or("profile"));
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        // Keep any config file of whoever is running the tests out of it
+        cmd.env("XDG_CONFIG_HOME", &home_dir)
+            .arg("--config")
+            .arg(&conf_file)
+            .arg("--profile")
+            .arg("small")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        if let Some(t) = x {
+            cmd.arg("-u").arg(t);
+        }
+
+        let run_out = cmd.output()?;
+
+        overviews.push(String::from_utf8(run_out.stdout)?);
+        found.push(common::read_report(&run_dir).lines().count());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(found, vec![1, 3]);
+    assert!(overviews[0].contains("Profile:"));
+    assert!(overviews[0].contains("Maximum Size:"));
+    Ok(())
+}
+
+
+#[test]
+// Search directories given on the command line replace the config file's dir rather than adding to
+// it, whether they are given with -d or listed at the end of the command line. Only the pair of
+// duplicates in the command line directory should be found.
+fn config_file_roots() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("config_file_roots");
+    let conf_dir = home_dir.join("conf_search");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&conf_dir, "good_conf_1.txt", common::GOOD_DATA);
+    common::write_file(&conf_dir, "good_conf_2.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "bad_in_1.txt", common::BAD_DATA);
+    common::write_file(&search_dir, "bad_in_2.txt", common::BAD_DATA);
+
+    let conf_file = common::write_file(&home_dir, "duff.toml",
+                                       &format!("dir = [\"{}\"]\n", conf_dir.display()));
+
+    let mut reports = Vec::new();
+
+    for x in ["dir", "root"].iter() {
+        let run_dir = out_dir.join(x);
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("--config")
+            .arg(&conf_file)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        if *x == "dir" {
+            cmd.arg("-d");
+        }
+
+        cmd.arg(&search_dir);
+
+        cmd.assert()
+            .success();
+
+        reports.push(common::read_report(&run_dir));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for rep_content in reports {
+        assert_eq!(rep_content.lines().count(), 3);
+        assert!(rep_content.contains("bad_in_1.txt"));
+        assert!(!rep_content.contains("good_conf_1.txt"));
+    }
+    Ok(())
+}
+
+
+#[test]
+// Config file settings are held to the same rules as the command line: an unknown hash_match mode,
+// unique alongside ref, a flag given a value and resume (which is command line only) should all be
+// refused, naming the file and option.
+// A flag switched on in a config file can be switched off again with --unset.
+fn config_file_checked() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("config_file_checked");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    let dir_str = format!("dir = [\"{}\"]\n", search_dir.display());
+    let bad_confs = [
+        ("mode.toml", "hash = \"old.arch\"\nhash_match = \"bogus\"\n", "hash_match can't be bogus"),
+        ("conflict.toml", "unique = true\nref = [\"/tmp\"]\n", "can't be used with"),
+        ("flag.toml", "archive = \"yes\"\n", "archive is a flag"),
+        ("resume.toml", "resume = \"old.log\"\n", "resume can only be given on the command line"),
+    ];
+
+    let mut errors = Vec::new();
+
+    for (x, y, _) in bad_confs.iter() {
+        let conf_file = common::write_file(&home_dir, x, &format!("{}{}", dir_str, y));
+
+        let run_out = common::duff()
+            .arg("--config")
+            .arg(&conf_file)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir)
+            .output()?;
+
+        // Errors are wrapped to the terminal's width, so undo that before looking at them.
+        let err_str = String::from_utf8(run_out.stderr)?.split_whitespace()
+            .collect::<Vec<&str>>().join(" ");
+        errors.push((run_out.status.success(), err_str));
+    }
+
+    let conf_file = common::write_file(&home_dir, "archive.toml",
+                                       &format!("{}archive = true\n", dir_str));
+
+    common::duff()
+        .arg("--config")
+        .arg(&conf_file)
+        .arg("--unset")
+        .arg("archive")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let n_arch = glob(&format!("{}/*.arch*", out_dir.display()))?.count();
+    let n_report = glob(&format!("{}/*.report", out_dir.display()))?.count();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for ((x, _, z), (success, err_str)) in bad_confs.iter().zip(errors.iter()) {
+        assert!(!success);
+        assert!(err_str.contains(x));
+        assert!(err_str.contains(z));
+    }
+
+    assert_eq!(n_arch, 0);
+    assert_eq!(n_report, 1);
+    Ok(())
+}
+
+
+#[test]
+// Subcommands: duff scan finds the pair of good files and saves a log, duff resume picks that log
+// up without traversing anything and finds them again, and duff report turns the report into CSV.
+fn scan_resume_and_report() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("scan_resume_and_report");
+    let search_dir = home_dir.join("search");
+    let scan_dir = home_dir.join("out").join("scan");
+    let resume_dir = home_dir.join("out").join("resume");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "bad_in_1.txt", common::BAD_DATA);
+    fs::create_dir_all(&scan_dir)?;
+    fs::create_dir_all(&resume_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("scan")
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("-g")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&scan_dir);
+
+    cmd.assert()
+        .success();
+
+    let log_file = glob(&format!("{}/*.log", scan_dir.display()))?.next().unwrap()?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("resume")
+        .arg(&log_file)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&resume_dir);
+
+    cmd.assert()
+        .success();
+
+    let scan_report = common::read_report(&scan_dir);
+    let resume_report = common::read_report(&resume_dir);
+
+    let rep_file = glob(&format!("{}/*.report", resume_dir.display()))?.next().unwrap()?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("report")
+        .arg(&rep_file)
+        .arg("-f")
+        .arg("csv");
+
+    let report_assert = cmd.assert()
+        .success();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(scan_report.lines().count(), 3);
+    assert_eq!(scan_report, resume_report);
+    report_assert.stdout(predicate::str::starts_with("group,number,name,path,size,mtime,group_id\n\
+                                                      1,1,good_in_1.txt,"));
+    Ok(())
+}
+
+
+#[test]
+// Search directories: one of the two directories holding the good files has a comma in its name.
+// Giving it with -d and the other one at the end of the command line should find the pair, as
+// should listing both in a --dirs-from file, with the extensions given as separate -e flags.
+fn dirs_with_commas() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("dirs_with_commas");
+    let comma_dir = home_dir.join("with,comma");
+    let plain_dir = home_dir.join("plain");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&comma_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&plain_dir, "good_in_2.dat", common::GOOD_DATA);
+    common::write_file(&plain_dir, "good_in_3.csv", common::GOOD_DATA);
+
+    let dirs_file = common::write_file(&home_dir, "dirs.txt", &format!("{}\n{}\n",
+                                       comma_dir.display(), plain_dir.display()));
+
+    let mut found = Vec::new();
+
+    for x in ["args", "list"].iter() {
+        let run_dir = out_dir.join(x);
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-e")
+            .arg("txt")
+            .arg("-e")
+            .arg("dat")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        if *x == "args" {
+            cmd.arg("-d").arg(&comma_dir).arg(&plain_dir);
+        } else {
+            cmd.arg("--dirs-from").arg(&dirs_file);
+        }
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&run_dir));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for x in found.iter() {
+        assert_eq!(x.lines().count(), 3);
+        assert!(x.contains("with,comma"));
+        assert!(!x.contains("good_in_3"));
+    }
+    Ok(())
+}
+
+
+#[test]
+// Archive maintenance: after archiving the hashes of the two good files one of them is deleted, so
+// pruning the archive should drop its entry and the stats should only count the one left. The
+// archive is relabelled as hashed with another algorithm first, which pruning has to keep.
+fn archive_prune_and_stats() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("archive_prune_and_stats");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    let good_2 = common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-a")
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let arch_file = glob(&format!("{}/*.arch", out_dir.display()))?.next().unwrap()?;
+    fs::remove_file(good_2)?;
+
+    let arch_content = fs::read_to_string(&arch_file)?;
+    fs::write(&arch_file, arch_content.replace("\"hash_algorithm\":\"xxh3_128\"",
+                                               "\"hash_algorithm\":\"md5\""))?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("archive")
+        .arg("prune")
+        .arg(&arch_file);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("Pruned 1 of 2 entries"));
+
+    let arch_content = fs::read_to_string(&arch_file)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("archive")
+        .arg("stats")
+        .arg(&arch_file);
+
+    let stats_assert = cmd.assert()
+        .success();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    stats_assert.stdout(predicate::str::is_match("Entries:\\s+1\n")?);
+    assert_eq!(arch_content.lines().count(), 2);
+    assert!(arch_content.starts_with("{\"duff_header\":"));
+    assert!(arch_content.contains("\"hash_algorithm\":\"md5\""));
+    assert!(arch_content.contains("good_in_1.txt"));
+    Ok(())
+}
+
+#[test]
+// Verifying an archive when one of its files can't be read back. good_in_1.txt is swapped for a
+// directory of the same name and its archive entry doctored to match the directory's size and mtime,
+// so it still looks current but hashing it fails. That has to count against the archive, not pass.
+fn archive_verify_unreadable() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("archive_verify_unreadable");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    let good_1 = common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("-a")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let arch_file = glob(&format!("{}/*.arch", out_dir.display()))?.next().unwrap()?;
+
+    fs::remove_file(&good_1)?;
+    fs::create_dir(&good_1)?;
+
+    let dir_meta = fs::metadata(&good_1)?;
+    let dir_mtime: chrono::DateTime<chrono::Utc> = dir_meta.modified()?.into();
+    let mut new_content = String::new();
+
+    for curr_line in fs::read_to_string(&arch_file)?.lines() {
+        let mut record: serde_json::Value = serde_json::from_str(curr_line)?;
+
+        if record["file_name"] == "good_in_1.txt" {
+            record["size"] = serde_json::json!(dir_meta.len());
+            record["mtime"] = serde_json::json!(dir_mtime);
+        }
+        new_content.push_str(&format!("{}\n", record));
+    }
+    fs::write(&arch_file, new_content)?;
+
+    let verify_assert = common::duff()
+        .arg("archive")
+        .arg("verify")
+        .arg(&arch_file)
+        .assert()
+        .failure();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    verify_assert.stdout(predicate::str::contains("UNREADABLE\t")
+        .and(predicate::str::contains("1 ok, 0 mismatched, 1 unreadable")));
+    Ok(())
+}
+
+// Extensions should match case-insensitively and only after a dot, compound extensions and groups
+// (built in and from a config file) should work, and excluded extensions should be skipped.
+#[test]
+fn extension_groups_and_excludes() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("extension_groups_and_excludes");
+    let search_dir = home_dir.join("search");
+    let conf_file = common::write_file(&home_dir, "duff.toml", "[ext_groups]\nmine = [\".FQ.GZ\"]\n");
+
+    for x in ["a.BAM", "b.bam", "notabam", "c.fastq.gz", "d.fq.gz", "e.gz"].iter() {
+        common::write_file(&search_dir, x, common::GOOD_DATA);
+    }
+
+    let runs: Vec<(&str, Vec<&str>)> = vec![
+        ("plain", vec!["-e", ".bam"]),
+        ("group", vec!["-e", "@sequencing", "--exclude-ext", "fq.gz"]),
+        ("exclude", vec!["--exclude-ext", "bam,@mine"]),
+        ("conf_group", vec!["-e", "@mine", "-e", "gz"]),
+    ];
+
+    let mut found = Vec::new();
+
+    for (x, y) in runs.iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("--config")
+            .arg(&conf_file)
+            .arg("-o")
+            .arg(&out_dir)
+            .args(y);
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&out_dir).lines().count());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    // plain: a.BAM, b.bam; group: a.BAM, b.bam, c.fastq.gz; exclude: notabam, c.fastq.gz, e.gz;
+    // conf_group: c.fastq.gz, d.fq.gz, e.gz
+    assert_eq!(found, vec![3, 4, 4, 4]);
+    Ok(())
+}
+
+// FIFOs should be skipped and counted rather than hashed (which would block forever), whether they
+// are found while searching or handed to us in a file list.
+#[cfg(unix)]
+#[test]
+#[ntest::timeout(60000)]
+fn special_files_skipped() {
+
+    let home_dir = common::test_dir("special_files_skipped");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    fs::create_dir_all(&out_dir).unwrap();
+
+    let fifo_path = search_dir.join("pipe.txt");
+    let mkfifo_ok = Command::new("mkfifo").arg(&fifo_path).status().unwrap().success();
+    let list_file = common::write_file(&home_dir, "files.txt", &format!("{}\n", fifo_path.display()));
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("--files-from")
+        .arg(&list_file)
+        .arg("--no_cache")
+        .arg("-p")
+        .arg("-o")
+        .arg(&out_dir);
+
+    let cmd_out = cmd.output().unwrap();
+    let rep_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir).unwrap();
+
+    assert!(mkfifo_ok);
+    assert!(cmd_out.status.success());
+    assert!(String::from_utf8_lossy(&cmd_out.stdout).lines()
+        .any(|x| x.starts_with("Special Files Skipped:") && x.ends_with(" 2")));
+    assert_eq!(rep_content.lines().count(), 3);
+}
+
+// With --empty the empty files and empty directories should each be reported as a group, whether or
+// not there are any duplicates, and without it they should be skipped.
+#[test]
+fn empty_files_and_dirs() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("empty_files_and_dirs");
+    let dupe_dir = home_dir.join("dupes");
+    let only_dir = home_dir.join("only_empty");
+
+    common::write_file(&dupe_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&dupe_dir, "good_in_2.txt", common::GOOD_DATA);
+
+    for x in ["empty_1.txt", "sub/empty_2.txt", "sub/empty_3.log"].iter() {
+        common::write_file(&dupe_dir, x, "");
+    }
+    fs::create_dir_all(dupe_dir.join("nothing_here"))?;
+    fs::create_dir_all(dupe_dir.join("sub/deeper/still_nothing"))?;
+
+    common::write_file(&only_dir, "empty_1.txt", "");
+    fs::create_dir_all(only_dir.join("nothing_here"))?;
+
+    let runs: Vec<(&str, &PathBuf, bool)> = vec![
+        ("dupes_empty", &dupe_dir, true),
+        ("dupes_plain", &dupe_dir, false),
+        ("only_empty", &only_dir, true),
+    ];
+
+    let mut found = Vec::new();
+    let mut empty_rep = String::new();
+
+    for (x, y, z) in runs.iter() {
+        let out_dir = home_dir.join("out").join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(y)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir);
+
+        if *z {
+            cmd.arg("--empty");
+        }
+
+        cmd.assert()
+            .success();
+
+        let rep_content = common::read_report(&out_dir);
+        found.push(rep_content.lines().count());
+
+        if *x == "dupes_empty" {
+            empty_rep = rep_content;
+        }
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    // 2 duplicates, 3 empty files and 2 empty directories (sub/deeper has something in it).
+    assert_eq!(found, vec![8, 3, 3]);
+    assert_eq!(empty_rep.lines().filter(|x| x.contains("\tnothing_here\t") ||
+        x.contains("\tstill_nothing\t")).count(), 2);
+    Ok(())
+}
+
+// Misnamed files should be recognized by their contents, filtered with --type and have their type
+// in the report.
+#[test]
+fn detect_file_types() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("detect_file_types");
+    let search_dir = home_dir.join("search");
+
+    // A gzipped FASTQ, the CRC and size at the end aren't checked when detecting types.
+    let mut fastq_gz = vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+    fastq_gz.extend(miniz_oxide::deflate::compress_to_vec(b"@read1\nACGT\n+\nIIII\n", 6));
+    fastq_gz.extend(&[0; 8]);
+
+    for x in ["reads_1.txt", "reads_2.tmp"].iter() {
+        fs::create_dir_all(&search_dir)?;
+        fs::write(search_dir.join(x), &fastq_gz)?;
+    }
+
+    common::write_file(&search_dir, "doc_1.dat", &format!("%PDF-1.4\n{}", common::GOOD_DATA));
+    common::write_file(&search_dir, "doc_2.bin", &format!("%PDF-1.4\n{}", common::GOOD_DATA));
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+
+    let runs: Vec<(&str, Vec<&str>)> = vec![
+        ("fastq", vec!["--type", "fastq.gz"]),
+        ("pdf_text", vec!["--type", "pdf,text"]),
+        ("all", vec!["--detect-types"]),
+    ];
+
+    let mut found = Vec::new();
+    let mut fastq_rep = String::new();
+
+    for (x, y) in runs.iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir)
+            .args(y);
+
+        cmd.assert()
+            .success();
+
+        let rep_content = common::read_report(&out_dir);
+        found.push(rep_content.lines().count());
+
+        if *x == "fastq" {
+            fastq_rep = rep_content;
+        }
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(found, vec![3, 5, 7]);
+    assert!(fastq_rep.lines().next().unwrap().ends_with("\tType\tGroup ID"));
+    assert_eq!(fastq_rep.lines().filter(|x| x.contains("\tfastq.gz\t")).count(), 2);
+    Ok(())
+}
+
+// Only files inside the mtime window, owned by the right user and with the right permission bits
+// should be considered.
+#[cfg(unix)]
+#[test]
+fn mtime_owner_perm_filters() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::time::{Duration, SystemTime};
+
+    let home_dir = common::test_dir("mtime_owner_perm_filters");
+    let search_dir = home_dir.join("search");
+
+    let two_years = SystemTime::now() - Duration::from_secs(2 * 365 * 86400);
+
+    for x in ["old_1.txt", "old_2.txt", "new_1.txt"].iter() {
+        let curr_file = common::write_file(&search_dir, x, common::GOOD_DATA);
+        fs::set_permissions(&curr_file, fs::Permissions::from_mode(0o644))?;
+
+        if x.starts_with("old") {
+            File::options().write(true).open(&curr_file)?.set_modified(two_years)?;
+        }
+    }
+    fs::set_permissions(search_dir.join("old_2.txt"), fs::Permissions::from_mode(0o600))?;
+
+    let my_uid = fs::metadata(&search_dir)?.uid();
+
+    let runs: Vec<(&str, Vec<String>)> = vec![
+        ("older", vec!["--older-than".to_string(), "1y".to_string()]),
+        ("newer", vec!["--newer-than".to_string(), "1y".to_string()]),
+        ("mine", vec!["--uid".to_string(), my_uid.to_string()]),
+        ("not_mine", vec!["--uid".to_string(), (my_uid + 1).to_string()]),
+        ("perm", vec!["--perm".to_string(), "644".to_string()]),
+        ("perm_any", vec!["--perm".to_string(), "/044".to_string(), "--older-than".to_string(),
+                          "2000-01-01".to_string()]),
+    ];
+
+    let mut found = Vec::new();
+
+    for (x, y) in runs.iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir)
+            .args(y);
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&out_dir).lines().count());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(found, vec![3, 1, 4, 1, 3, 1]);
+    Ok(())
+}
+
+// Files whose names aren't valid UTF-8 used to be dropped with an error, now they should be found,
+// written to the archive without loss and shown escaped in the report.
+#[cfg(unix)]
+#[test]
+fn non_utf8_file_name() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let home_dir = common::test_dir("non_utf8_file_name");
+    let search_dir = home_dir.join("search");
+    let arch_dir = home_dir.join("out_arch");
+    let run_dir = home_dir.join("out_run");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    fs::write(search_dir.join(OsStr::from_bytes(b"good_\xff_2.txt")), common::GOOD_DATA)?;
+    fs::create_dir_all(&arch_dir)?;
+    fs::create_dir_all(&run_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-a")
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&arch_dir);
+
+    cmd.assert()
+        .success();
+
+    let arch_file = glob(&format!("{}/*.arch", arch_dir.display()))?.next().unwrap()?;
+    let arch_content = fs::read_to_string(&arch_file)?;
+
+    // Read the archive back in, which needs the hex encoded name to round trip.
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("-x")
+        .arg(&arch_file)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&run_dir);
+
+    cmd.assert()
+        .success();
+
+    let rep_content = common::read_report(&run_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert!(arch_content.contains("\"file_name\":{\"hex\":\"676f6f645fff5f322e747874\"}"));
+    assert_eq!(rep_content.lines().count(), 3);
+    assert!(rep_content.contains("good_\\xFF_2.txt"));
+    Ok(())
+}
+
+// A file literally named good_\xFF_1.txt and one with the byte 0xFF in its name should still be
+// told apart in the report, the backslash in the first is doubled.
+#[cfg(unix)]
+#[test]
+fn non_utf8_escape_backslash() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let home_dir = common::test_dir("non_utf8_escape_backslash");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_\\xFF_1.txt", common::GOOD_DATA);
+    fs::write(search_dir.join(OsStr::from_bytes(b"good_\xff_1.txt")), common::GOOD_DATA)?;
+    fs::create_dir_all(&out_dir)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("-d")
+        .arg(&search_dir)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir);
+
+    cmd.assert()
+        .success();
+
+    let rep_content = common::read_report(&out_dir);
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(rep_content.lines().count(), 3);
+    assert!(rep_content.contains("good_\\\\xFF_1.txt"));
+    assert!(rep_content.lines().any(|x| x.contains("good_\\xFF_1.txt") && !x.contains("\\\\")));
+    Ok(())
+}
+
+// A search directory whose name isn't valid UTF-8 should be searched like any other, whether it is
+// given with -d, listed at the end of the command line or read from --dirs-from, and should be
+// written to an archive's header without loss.
+#[cfg(unix)]
+#[test]
+fn non_utf8_root() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let home_dir = common::test_dir("non_utf8_root");
+    let search_dir = home_dir.join(OsStr::from_bytes(b"search_\xff"));
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+
+    let list_file = home_dir.join("dirs.txt");
+    fs::write(&list_file, [search_dir.as_os_str().as_bytes(), b"\n"].concat())?;
+
+    let mut found = Vec::new();
+
+    for x in ["dir", "root", "list"].iter() {
+        let run_dir = out_dir.join(x);
+        fs::create_dir_all(&run_dir)?;
+
+        let mut cmd = common::duff();
+
+        match *x {
+            "dir" => cmd.arg("-d").arg(&search_dir),
+            "root" => cmd.arg(&search_dir),
+            _ => cmd.arg("--dirs-from").arg(&list_file)
+        };
+
+        cmd.arg("-a")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&run_dir);
+
+        let run_out = cmd.output()?;
+
+        let arch_file = glob(&format!("{}/*.arch", run_dir.display()))?.next().unwrap()?;
+
+        found.push((run_out.status.success(), common::read_report(&run_dir),
+                    fs::read_to_string(&arch_file)?));
+    }
+
+    let root_hex: String = fs::canonicalize(&search_dir)?.as_os_str().as_bytes().iter()
+        .map(|x| format!("{:02x}", x)).collect();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for (ok, rep_content, arch_content) in found {
+        assert!(ok);
+        assert_eq!(rep_content.lines().count(), 3);
+        assert!(rep_content.contains("search_\\xFF"));
+        assert!(arch_content.contains(&format!("\"roots\":[{{\"hex\":\"{}\"}}]", root_hex)));
+    }
+    Ok(())
+}
+
+// SIGTERM part way through a run: DuFF is held up reading its list of files from stdin, signalled,
+// then handed the list. It should stop there, exit with 130, finish off its log and archive and
+// mark the report as incomplete. In find-unique mode nothing can be trusted to be unique, so the
+// report shouldn't list any files at all. As the search never finished, DuFF should say the run
+// can't be resumed, and resuming from the log should be refused.
+#[cfg(unix)]
+#[test]
+fn sigterm_partial_report() -> Result<(), Box<dyn std::error::Error>> {
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let home_dir = common::test_dir("sigterm_partial_report");
+    let files_dir = home_dir.join("files");
+
+    let good_1 = common::write_file(&files_dir, "good_in_1.txt", common::GOOD_DATA);
+    let good_2 = common::write_file(&files_dir, "good_in_2.txt", common::GOOD_DATA);
+    let bad_1 = common::write_file(&files_dir, "bad_in_1.txt", common::BAD_DATA);
+    let list_data = format!("{}\n{}\n{}\n", good_1.display(), good_2.display(), bad_1.display());
+
+    let mut found = Vec::new();
+
+    for x in ["dupes", "unique"].iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("--files-from")
+            .arg("-")
+            .arg("-g")
+            .arg("-a")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        if *x == "unique" {
+            cmd.arg("-U");
+        }
+
+        let mut child = cmd.spawn()?;
+
+        // The log is opened once DuFF is ready to wind down gracefully, wait for it before
+        // signalling.
+        let start = Instant::now();
+
+        while glob(&format!("{}/*.part", out_dir.display()))?.count() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        Command::new("kill").arg("-TERM").arg(child.id().to_string()).status()?;
+        std::thread::sleep(Duration::from_millis(200));
+
+        child.stdin.take().unwrap().write_all(list_data.as_bytes())?;
+        let run_out = child.wait_with_output()?;
+        let err_str = String::from(std::str::from_utf8(&run_out.stderr)?).split_whitespace()
+            .collect::<Vec<&str>>().join(" ");
+
+        let n_part = glob(&format!("{}/*.part", out_dir.display()))?.count();
+        let n_done = glob(&format!("{}/*.log", out_dir.display()))?.count() +
+            glob(&format!("{}/*.arch", out_dir.display()))?.count();
+
+        let report = common::read_report(&out_dir);
+
+        // The search never finished, so the log can't be resumed.
+        let log_file = glob(&format!("{}/*.log", out_dir.display()))?.next().unwrap()?;
+        let resume_ok = common::duff().arg("resume").arg(&log_file).arg("-o").arg(&out_dir)
+            .output()?.status.success();
+
+        found.push((run_out.status.code(), n_part, n_done, report,
+                    err_str.contains("can't be resumed"), resume_ok));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for (code, n_part, n_done, report, told, resume_ok) in found.iter() {
+        assert_eq!(*code, Some(130));
+        assert_eq!(*n_part, 0);
+        assert_eq!(*n_done, 2);
+        assert!(report.starts_with("# INCOMPLETE"));
+        assert!(!report.contains("_in_"));
+        assert!(*told);
+        assert!(!*resume_ok);
+    }
+
+    assert!(found[1].3.contains("no files are listed"));
+    Ok(())
+}
+
+// Archives written in older format versions should still be read and migrated: a version 1 archive
+// (no header, no dev, inode or ctime) and a version 2 one, along with one we can't parse at all.
+#[test]
+fn archive_migrate_legacy() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("archive_migrate_legacy");
+    let record = "{\"file_name\":\"a.txt\",\"dir_path\":\"/tmp\",\"file_path\":\"/tmp/a.txt\",\
+                  \"size\":4,\"hash\":\"abc\",\"mtime\":\"2020-01-01T00:00:00Z\"}";
+    let v2_header = "{\"duff_header\":{\"format_version\":2,\"duff_version\":\"0.7.0\",\
+                     \"hash_algorithm\":\"xxh3_128\",\"host\":\"old\",\"roots\":[\"/tmp\"],\
+                     \"kind\":\"archive\",\"created\":\"2020-01-01T00:00:00Z\"}}";
+    let v2_record = "{\"file_name\":\"b.txt\",\"dir_path\":\"/tmp\",\"file_path\":\"/tmp/b.txt\",\
+                     \"size\":4,\"hash\":\"abc\",\"mtime\":\"2020-01-01T00:00:00Z\",\"dev\":7,\
+                     \"inode\":42,\"ctime\":1577836800000000000}";
+
+    // An archive from before headers existed, a version 2 one and one with a line we can't parse
+    let legacy = common::write_file(&home_dir, "legacy.arch", &format!("{}\n", record));
+    let v2 = common::write_file(&home_dir, "v2.arch", &format!("{}\n{}\n", v2_header, v2_record));
+    let broken = common::write_file(&home_dir, "broken.arch", &format!("{}\nnot json\n", record));
+
+    let mut cmd = common::duff();
+
+    cmd.arg("archive")
+        .arg("migrate")
+        .arg(&legacy);
+
+    let migrate_assert = cmd.assert()
+        .success();
+
+    let arch_content = fs::read_to_string(&legacy)?;
+
+    let v2_assert = common::duff()
+        .arg("archive")
+        .arg("migrate")
+        .arg(&v2)
+        .assert()
+        .success();
+
+    let v2_content = fs::read_to_string(&v2)?;
+
+    let mut cmd = common::duff();
+
+    cmd.arg("archive")
+        .arg("stats")
+        .arg(&broken);
+
+    let broken_assert = cmd.assert()
+        .failure();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    migrate_assert.stdout(predicate::str::starts_with("Migrated 1 entries from format version 1"));
+    broken_assert.stderr(predicate::str::contains("at line 2"));
+    assert!(arch_content.starts_with("{\"duff_header\":{\"format_version\":4"));
+    assert!(arch_content.contains("/tmp/a.txt"));
+    assert!(arch_content.contains("\"dev\":0,\"inode\":0,\"ctime\":0"));
+    v2_assert.stdout(predicate::str::starts_with("Migrated 1 entries from format version 2"));
+    assert!(v2_content.starts_with("{\"duff_header\":{\"format_version\":4"));
+    assert!(v2_content.contains("\"file_path\":\"/tmp/b.txt\""));
+    assert!(v2_content.contains("\"dev\":7,\"inode\":42,\"ctime\":1577836800000000000"));
+    Ok(())
+}
+
+// By default files found through a symlinked directory keep the symlinked path, with the metadata
+// lookups DuFF made and saved counted at the end, while --canonicalize resolves every path.
+#[cfg(unix)]
+#[test]
+fn canonicalize_paths() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("canonicalize_paths");
+    let search_dir = home_dir.join("search");
+    let other_dir = home_dir.join("elsewhere");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&other_dir, "good_in_2.txt", common::GOOD_DATA);
+    std::os::unix::fs::symlink(&other_dir, search_dir.join("linked"))?;
+
+    // The search directory itself is always canonicalized.
+    let linked_dir = fs::canonicalize(&search_dir)?.join("linked");
+    let other_dir = fs::canonicalize(&other_dir)?;
+
+    let mut results = Vec::new();
+
+    for (run, canon) in [("plain", false), ("canon", true)].iter() {
+        let out_dir = home_dir.join(run);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-p")
+            .arg("-o")
+            .arg(&out_dir);
+
+        if *canon {
+            cmd.arg("--canonicalize");
+        }
+
+        let cmd_out = cmd.output()?;
+        results.push((cmd_out.status.success(), String::from_utf8_lossy(&cmd_out.stdout).to_string(),
+                      common::read_report(&out_dir)));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    let (plain_ok, plain_stdout, plain_rep) = &results[0];
+    let (canon_ok, canon_stdout, canon_rep) = &results[1];
+
+    assert!(plain_ok);
+    assert!(plain_rep.contains(&format!("good_in_2.txt\t{}", linked_dir.display())));
+    assert!(plain_stdout.lines().any(|x| x.starts_with("Metadata Lookups Saved:")));
+    assert!(plain_stdout.lines().any(|x| x.starts_with("Path Canonicalizations Skipped:")));
+
+    assert!(canon_ok);
+    assert!(canon_rep.contains(&format!("good_in_2.txt\t{}", other_dir.display())));
+    assert!(!canon_stdout.lines().any(|x| x.starts_with("Path Canonicalizations Skipped:")));
+
+    Ok(())
+}
+
+// The depth limits should count from the search directory, hidden files and directories should be
+// skipped on request, and ignore files should be honoured with deeper ones able to whitelist what
+// shallower ones ignore.
+#[test]
+fn depth_hidden_and_ignore_files() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("depth_hidden_and_ignore_files");
+    let search_dir = home_dir.join("search");
+
+    for x in ["top.txt", "a/mid.txt", "a/b/deep.txt", ".hidden/h.txt", ".dot.txt", "build/x.txt",
+              "keep.log", "a/k.log"].iter() {
+        common::write_file(&search_dir, x, common::GOOD_DATA);
+    }
+
+    common::write_file(&search_dir, ".gitignore", "build/\n");
+    common::write_file(&search_dir, ".duffignore", "*.log\n");
+    common::write_file(&search_dir, "a/.gitignore", "!k.log\n");
+
+    let runs: Vec<(&str, Vec<&str>)> = vec![
+        ("all", vec![]),
+        ("max_depth", vec!["--max-depth", "2"]),
+        ("min_depth", vec!["--min-depth", "2"]),
+        ("hidden", vec!["--skip-hidden"]),
+        ("ignore", vec!["--ignore-files"]),
+        ("combined", vec!["--min-depth", "2", "--max-depth", "2", "--skip-hidden",
+                          "--ignore-files"]),
+    ];
+
+    let mut found = Vec::new();
+
+    for (x, y) in runs.iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(&out_dir)
+            .args(y);
+
+        cmd.assert()
+            .success();
+
+        found.push(common::read_report(&out_dir).lines().count());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    // all: 8 copies; max_depth: all but deep.txt; min_depth: mid.txt, deep.txt, h.txt, x.txt,
+    // k.log; hidden: all but h.txt and .dot.txt; ignore: all but x.txt and keep.log; combined:
+    // mid.txt and k.log
+    assert_eq!(found, vec![9, 8, 6, 7, 7, 3]);
+    Ok(())
+}
+
+// Reports should come out the same whatever the number of threads, with the biggest groups first
+// and the same group ids from run to run.
+#[test]
+fn deterministic_report_order() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("deterministic_report_order");
+    let search_dir = home_dir.join("search");
+
+    for x in 0..6 {
+        let sub_dir = search_dir.join(format!("dir_{}", x));
+        common::write_file(&sub_dir, "good.txt", common::GOOD_DATA);
+        common::write_file(&sub_dir, "bad.txt", common::BAD_DATA);
+        common::write_file(&sub_dir, "small.txt", "tiny");
+    }
+
+    let mut reports = Vec::new();
+
+    for (x, jobs) in [("one", "1"), ("four", "4"), ("eight", "8")].iter() {
+        let out_dir = home_dir.join(x);
+        fs::create_dir_all(&out_dir)?;
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("--no_cache")
+            .arg("-j")
+            .arg(jobs)
+            .arg("-o")
+            .arg(&out_dir);
+
+        cmd.assert()
+            .success();
+
+        reports.push(common::read_report(&out_dir));
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert_eq!(reports[0], reports[1]);
+    assert_eq!(reports[0], reports[2]);
+
+    let sizes: Vec<u128> = reports[0].lines().skip(1)
+        .map(|x| x.split('\t').nth(4).unwrap().parse().unwrap())
+        .collect();
+    let mut sorted_sizes = sizes.clone();
+    sorted_sizes.sort_by(|a, b| b.cmp(a));
+
+    assert_eq!(sizes.len(), 18);
+    assert_eq!(sizes, sorted_sizes);
+    assert!(reports[0].lines().skip(1).all(|x| x.rsplit('\t').next().unwrap().len() == 16));
+    Ok(())
+}
+
+// duff diff should pick out the new, resolved and changed groups between two runs, whether the
+// earlier run is given as its report or its archive.
+#[test]
+fn diff_runs() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("diff_runs");
+    let search_dir = home_dir.join("search");
+    let old_dir = home_dir.join("old");
+    let new_dir = home_dir.join("new");
+
+    common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "good_in_2.txt", common::GOOD_DATA);
+    common::write_file(&search_dir, "bad_in_1.txt", common::BAD_DATA);
+    common::write_file(&search_dir, "bad_in_2.txt", common::BAD_DATA);
+    fs::create_dir_all(&old_dir)?;
+    fs::create_dir_all(&new_dir)?;
+
+    for (x, out_dir) in ["old", "new"].iter().zip([&old_dir, &new_dir].iter()) {
+
+        // In between the runs one copy of the bad file goes, the good file gets another copy and
+        // a new pair of files shows up.
+        if *x == "new" {
+            fs::remove_file(search_dir.join("bad_in_2.txt"))?;
+            common::write_file(&search_dir, "good_in_3.txt", common::GOOD_DATA);
+            common::write_file(&search_dir, "new_1.txt", "something new");
+            common::write_file(&search_dir, "new_2.txt", "something new");
+        }
+
+        let mut cmd = common::duff();
+
+        cmd.arg("-d")
+            .arg(&search_dir)
+            .arg("-a")
+            .arg("--no_cache")
+            .arg("-o")
+            .arg(out_dir);
+
+        cmd.assert()
+            .success();
+    }
+
+    let new_rep = glob(&format!("{}/*.report", new_dir.display()))?.next().unwrap()?;
+    let mut outputs = Vec::new();
+
+    for x in ["report", "arch"].iter() {
+        let old_file = glob(&format!("{}/*.{}", old_dir.display(), x))?.next().unwrap()?;
+
+        let cmd_out = common::duff()
+            .arg("diff")
+            .arg(&old_file)
+            .arg(&new_rep)
+            .output()?;
+
+        assert!(cmd_out.status.success());
+        outputs.push(String::from_utf8_lossy(&cmd_out.stdout).to_string());
+    }
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    for x in outputs.iter() {
+        let summary = |name: &str| x.lines().find(|y| y.starts_with(name))
+            .and_then(|y| y.split_whitespace().last())
+            .map(|y| y.to_string());
+
+        assert_eq!(summary("New Groups:"), Some(String::from("1")));
+        assert_eq!(summary("Resolved Groups:"), Some(String::from("1")));
+        assert_eq!(summary("Groups That Gained Copies:"), Some(String::from("1")));
+        assert_eq!(summary("Groups That Lost Copies:"), Some(String::from("0")));
+        assert!(x.lines().any(|y| y.trim().starts_with("+ ") && y.ends_with("good_in_3.txt")));
+        assert!(x.lines().any(|y| y.trim().ends_with("new_2.txt")));
+    }
+
+    Ok(())
+}
+
+// Marks each copy in a CSV report (from duff report) to be kept if it's the first in its group, or
+// removed otherwise, unless remove_all is set, in which case every copy is marked for removal.
+fn mark_csv(csv_str: &str, remove_all: bool) -> String {
+    let mut lines = csv_str.lines();
+    let mut out_str = format!("{},action\n", lines.next().unwrap());
+
+    for x in lines {
+        let first = x.split(',').nth(1) == Some("1");
+        let action = if !remove_all && first { "keep" } else { "remove" };
+        out_str.push_str(&format!("{},{}\n", x, action));
+    }
+
+    out_str
+}
+
+// duff act should refuse to touch anything when a group has no copy kept or a file changed since
+// the report was written, and otherwise carry out the marked actions.
+#[test]
+fn act_on_marked_report() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("act_on_marked_report");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+    let move_dir = home_dir.join("moved");
+
+    for x in ["good_in_1.txt", "good_in_2.txt", "good_in_3.txt"].iter() {
+        common::write_file(&search_dir, x, common::GOOD_DATA);
+    }
+    common::write_file(&search_dir, "bad_in_1.txt", common::BAD_DATA);
+    common::write_file(&search_dir, "bad_in_2.txt", common::BAD_DATA);
+    fs::create_dir_all(&out_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let rep_file = glob(&format!("{}/*.report", out_dir.display()))?.next().unwrap()?;
+    let csv_out = common::duff().arg("report").arg(&rep_file).arg("-f").arg("csv")
+        .output()?;
+    let csv_str = String::from_utf8_lossy(&csv_out.stdout).to_string();
+
+    let act = |marked: &str, name: &str, extra: &[&str]| -> std::process::Output {
+        let marked_file = common::write_file(&home_dir, name, marked);
+
+        common::duff()
+            .arg("act")
+            .arg(&marked_file)
+            .arg("--verify-hash")
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    // Everything marked for removal, then a file changed since the report.
+    let all_out = act(&mark_csv(&csv_str, true), "all.csv", &[]);
+    common::write_file(&search_dir, "good_in_3.txt", common::GOOD_DATA);
+    let changed_out = act(&mark_csv(&csv_str, false), "changed.csv", &[]);
+    let n_after_refusals = fs::read_dir(&search_dir)?.count();
+
+    // Then a fresh report, acted on by moving the extra copies away.
+    let new_out = home_dir.join("new_out");
+    fs::create_dir_all(&new_out)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&new_out)
+        .assert()
+        .success();
+
+    let rep_file = glob(&format!("{}/*.report", new_out.display()))?.next().unwrap()?;
+    let csv_out = common::duff().arg("report").arg(&rep_file).arg("-f").arg("csv")
+        .output()?;
+    let csv_str = String::from_utf8_lossy(&csv_out.stdout).to_string();
+    let move_out = act(&mark_csv(&csv_str, false), "move.csv",
+                       &["-m", "move", "--move-to", move_dir.to_str().unwrap()]);
+
+    let mut left: Vec<String> = fs::read_dir(&search_dir)?
+        .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    left.sort();
+    let n_moved = glob(&format!("{}/**/*.txt", move_dir.display()))?.count();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert!(!all_out.status.success());
+    assert!(String::from_utf8_lossy(&all_out.stderr).contains("every copy is marked for removal"));
+    assert!(!changed_out.status.success());
+    assert!(String::from_utf8_lossy(&changed_out.stderr).contains("good_in_3.txt: modified at"));
+    assert_eq!(n_after_refusals, 5);
+
+    assert!(move_out.status.success());
+    assert_eq!(left, vec!["bad_in_1.txt", "good_in_1.txt"]);
+    assert_eq!(n_moved, 3);
+    Ok(())
+}
+
+// Names that aren't valid UTF-8 or have a backslash in them are escaped in reports, duff act should
+// undo that and act on the files themselves.
+#[cfg(unix)]
+#[test]
+fn act_on_escaped_names() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let home_dir = common::test_dir("act_on_escaped_names");
+    let search_dir = home_dir.join("search");
+    let out_dir = home_dir.join("out");
+
+    common::write_file(&search_dir, "plain.txt", common::GOOD_DATA);
+    fs::write(search_dir.join(OsStr::from_bytes(b"good_\xff.txt")), common::GOOD_DATA)?;
+    fs::write(search_dir.join("back\\slash.txt"), common::GOOD_DATA)?;
+    fs::create_dir_all(&out_dir)?;
+
+    common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir)
+        .assert()
+        .success();
+
+    let rep_file = glob(&format!("{}/*.report", out_dir.display()))?.next().unwrap()?;
+    let csv_out = common::duff().arg("report").arg(&rep_file).arg("-f").arg("csv")
+        .output()?;
+    let csv_str = String::from_utf8_lossy(&csv_out.stdout).to_string();
+
+    let mut lines = csv_str.lines();
+    let mut marked = format!("{},action\n", lines.next().unwrap());
+
+    for x in lines {
+        let action = if x.split(',').nth(2) == Some("plain.txt") { "keep" } else { "remove" };
+        marked.push_str(&format!("{},{}\n", x, action));
+    }
+
+    let marked_file = common::write_file(&home_dir, "marked.csv", &marked);
+    let act_out = common::duff()
+        .arg("act")
+        .arg(&marked_file)
+        .arg("--verify-hash")
+        .output()?;
+
+    let left: Vec<String> = fs::read_dir(&search_dir)?
+        .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert!(csv_str.contains("good_\\xFF.txt"));
+    assert!(csv_str.contains("back\\\\slash.txt"));
+    assert!(act_out.status.success());
+    assert_eq!(left, vec!["plain.txt"]);
+    Ok(())
+}
+
+// A file reached under two paths, through a symlink to it, a symlinked directory from a second
+// search directory, or a hard link, is still the one file and shouldn't be reported as its own copy.
+#[cfg(unix)]
+#[test]
+fn same_file_two_paths() -> Result<(), Box<dyn std::error::Error>> {
+
+    let home_dir = common::test_dir("same_file_two_paths");
+    let search_dir = home_dir.join("search");
+    let other_dir = home_dir.join("other");
+    let out_dir = home_dir.join("out");
+
+    let real_file = common::write_file(&search_dir, "good_in_1.txt", common::GOOD_DATA);
+    std::os::unix::fs::symlink(&real_file, search_dir.join("link_to_1.txt"))?;
+    fs::hard_link(&real_file, search_dir.join("hard_1.txt"))?;
+    fs::create_dir_all(&other_dir)?;
+    std::os::unix::fs::symlink(&search_dir, other_dir.join("linked"))?;
+    fs::create_dir_all(&out_dir)?;
+
+    let run_out = common::duff()
+        .arg("-d")
+        .arg(&search_dir)
+        .arg(&other_dir)
+        .arg("--no_cache")
+        .arg("-o")
+        .arg(&out_dir)
+        .output()?;
+
+    let report = common::read_report(&out_dir);
+    let left = fs::read_dir(&search_dir)?.count();
+
+    // Clean up before doing assert
+    fs::remove_dir_all(&home_dir)?;
+
+    assert!(run_out.status.success());
+    assert!(!report.contains("good_in_1.txt"));
+    assert_eq!(left, 3);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Helper functions shared by the DuFF command line tests. Most of the older tests in cli.rs still
+// set up their own files by hand, newer tests lean on these to cut down on the copy and paste.
+
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::fs::{self, File};
+use std::process::{self, Command};
+use assert_cmd::prelude::*;
+use glob::glob;
+
+// Content used for files that should be reported as duplicates of one another.
+pub const GOOD_DATA: &str = "Same content\nSame extension\nsame file size\nSame start bytes\nSame end \
+                             bytes\nSame hash";
+
+// Content used for files that share nothing with GOOD_DATA except where they live.
+pub const BAD_DATA: &str = "Same content\nSame extension\nsame file size\nNo, this is new\nSame start \
+                            bytes\nSame end bytes\nSame hash";
+
+// Build the command to run DuFF with, its hash cache and config file directories pointed at a
+// directory of our own, so running the tests never reads or writes the ones in the developer's home
+// directory. Tests that care about either still point DuFF at their own.
+pub fn duff() -> Command {
+    let xdg_dir = std::env::temp_dir().join(format!("duff_tests_{}", process::id()));
+
+    let mut cmd = Command::cargo_bin("duff").unwrap();
+    cmd.env("XDG_CACHE_HOME", xdg_dir.join("cache"))
+        .env("XDG_CONFIG_HOME", xdg_dir.join("config"));
+
+    cmd
+}
+
+// Create (or re-create) a test directory named dir_name inside tests/duff_test_data and hand back
+// its full path.
+pub fn test_dir(dir_name: &str) -> PathBuf {
+    let mut home_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    home_dir.push(format!("./tests/duff_test_data/{}", dir_name));
+
+    if home_dir.exists() {
+        fs::remove_dir_all(&home_dir).unwrap();
+    }
+    fs::create_dir_all(&home_dir).unwrap();
+
+    home_dir
+}
+
+// Write data out to file_name inside of dir, creating any missing parent directories along the way,
+// and return the full path of the new file.
+pub fn write_file(dir: &Path, file_name: &str, data: &str) -> PathBuf {
+    let file_path = dir.join(file_name);
+    fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+
+    let mut file = File::create(&file_path).unwrap();
+    write!(&mut file, "{}", data).unwrap();
+
+    file_path
+}
+
+// Find the report file DuFF wrote into out_dir and return its contents.
+pub fn read_report(out_dir: &Path) -> String {
+    let rep_str = format!("{}/*.report", out_dir.display());
+
+    let mut rep_file = glob(&rep_str).unwrap();
+    let curr_file = rep_file.next().unwrap().unwrap();
+
+    fs::read_to_string(curr_file).unwrap()
+}
+
+// Swap every hash in the JSON lines file (archive, log or hash cache) at json_file out for the same
+// made up one, so a later run that reuses them reports files as duplicates that really aren't.
+pub fn fake_hashes(json_file: &Path) {
+    let mut new_content = String::new();
+
+    for curr_line in fs::read_to_string(json_file).unwrap().lines() {
+        match curr_line.find("\"hash\":\"") {
+            Some(t) => {
+                let hash_end = t + 8 + curr_line[t + 8..].find('"').unwrap();
+                new_content.push_str(&format!("{}42{}\n", &curr_line[..t + 8],
+                                              &curr_line[hash_end..]));
+            }
+            None => new_content.push_str(&format!("{}\n", curr_line))
+        }
+    }
+
+    fs::write(json_file, new_content).unwrap();
+}